@@ -148,13 +148,24 @@ fn generate_function(args: Args, block: &Block,
         log::#level!(#fmt_end);
     };
 
+    // The log scope is only entered for test cases, see captains_log::scope
     if async_context {
-        let block = quote::quote_spanned!(block.span()=>
+        let body = quote::quote_spanned!(block.span()=>
             #begin_expr
+            #[allow(clippy::let_unit_value)]
             let __ret_value = async { #block }.await;
             #end_expr
             __ret_value
         );
+        let block = quote::quote_spanned!(block.span()=>
+            #[cfg(not(test))]
+            let __ret_value = { #body };
+            #[cfg(test)]
+            let __ret_value = captains_log::scope::LogScope::instrument(#fn_name, async move {
+                #body
+            }).await;
+            __ret_value
+        );
         if async_keyword { // normal async fn
             return block.into();
         } else { // async_trait
@@ -166,7 +177,10 @@ fn generate_function(args: Args, block: &Block,
         }
     } else {
         return quote::quote_spanned!(block.span()=>
+            #[cfg(test)]
+            let __log_scope = captains_log::scope::LogScope::enter(#fn_name);
             #begin_expr
+            #[allow(clippy::let_unit_value, clippy::redundant_closure_call)]
             let __ret_value = (move ||#block)();
             #end_expr
             __ret_value
@@ -213,6 +227,11 @@ fn output_stream(input: &ItemFn, func_body: proc_macro2::TokenStream) -> proc_ma
 ///
 /// Supports async fn and async-trait >=0.1.44.
 ///
+/// Under `cfg(test)`, the outer most `#[logfn]` enters a log scope (named after the test case),
+/// so that logs of test cases running in parallel can be isolated. Other code expands without
+/// the scope.
+/// Refer to [captains_log::scope](https://docs.rs/captains-log/latest/captains_log/scope/index.html).
+///
 /// # Examples
 ///
 /// ``` rust
@@ -220,7 +239,7 @@ fn output_stream(input: &ItemFn, func_body: proc_macro2::TokenStream) -> proc_ma
 /// use captains_log::{recipe, logfn};
 /// use log::*;
 ///
-/// let builder = recipe::raw_file_logger("/tmp", "log_test", log::Level::Debug).test();
+/// let builder = recipe::raw_file_logger("/tmp/log_test.log", log::Level::Debug).test();
 /// builder.build().expect("setup_log");
 ///
 /// // default log level to be info
@@ -268,7 +287,7 @@ fn output_stream(input: &ItemFn, func_body: proc_macro2::TokenStream) -> proc_ma
 /// // In order make logs available.
 /// #[fixture]
 /// fn setup() {
-///     let builder = recipe::raw_file_logger("/tmp", "log_rstest", log::Level::Debug).test();
+///     let builder = recipe::raw_file_logger("/tmp/log_rstest.log", log::Level::Debug).test();
 ///     builder.build().expect("setup_log");
 /// }
 ///
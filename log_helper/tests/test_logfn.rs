@@ -7,7 +7,7 @@ use async_trait::async_trait;
 mod common;
 
 fn setup_log() {
-    let builder = recipe::raw_file_logger("/tmp", "log_test", log::Level::Debug).test();
+    let builder = recipe::raw_file_logger("/tmp/log_test.log", log::Level::Debug).test();
     common::clear_test_files(&builder);
     builder.build().expect("setup_log");
 }
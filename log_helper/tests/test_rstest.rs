@@ -11,7 +11,7 @@ mod common;
 #[fixture]
 fn setup() {
     INIT.call_once(|| {
-        let builder = recipe::raw_file_logger("/tmp", "log_rstest", log::Level::Debug).test();
+        let builder = recipe::raw_file_logger("/tmp/log_rstest.log", log::Level::Debug).test();
         common::clear_test_files(&builder);
         builder.build().expect("setup_log");
     });
@@ -1,25 +1,78 @@
 use crate::{
-    config::{LogFormat, SinkConfigBuild, SinkConfigTrait},
+    config::{Builder, LogFormat, SinkConfigBuild, SinkConfigTrait},
+    disk_guard::{DiskGuard, DiskGuardState, GuardRoute},
+    formatter::{RecordContext, ThreadContext},
     log_impl::{LogSink, LogSinkTrait},
+    metrics::{SinkMetrics, SinkStats},
+    report::{report_error, SinkErrorKind},
     rotation::*,
-    time::Timer,
+    time::{Clock, Timer},
 };
-use log::{Level, Record};
-use std::fs::metadata;
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Local};
+use log::{kv, Level, Record};
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::hash::{Hash, Hasher};
 use std::os::unix::prelude::*;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Once};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Once,
+};
 use std::time::{Duration, SystemTime};
 
-use crate::file_impl::open_file;
-use crossfire::{mpsc, MTx, RecvTimeoutError, Rx};
+use crate::file_impl::{
+    file_create_time, record_create_time, write_fd, CreatedDirs, FileOptions, FileSync, PathWatch,
+    SyncPolicy,
+};
+use crossfire::{mpmc, MRx, MTx, RecvTimeoutError, TrySendError};
 use std::thread;
 
 /// Limit to 4k buf size, so that during reload or graceful restart,
 /// the line will not be break.
 const FLUSH_SIZE_DEFAULT: usize = 4096;
 
+/// The number of lines waiting for the writer thread.
+const QUEUE_SIZE_DEFAULT: usize = 1024;
+
+/// What to do when the queue of [LogBufFile] is full, usually because the disk is stalled.
+///
+/// The dropped lines are counted by [GlobalLogger::dropped()](crate::GlobalLogger::dropped()),
+/// and a warning "N lines dropped" is written once the queue drains.
+#[derive(Hash, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Backpressure {
+    /// The logging threads block until there's space in the queue.
+    #[default]
+    Block,
+    /// Drop the line being logged.
+    DropNewest,
+    /// Drop the oldest line in the queue.
+    DropOldest,
+    /// Drop the line being logged when its level is below the level (for example Info and Debug
+    /// for `DropBelow(Level::Warn)`), block for the others.
+    DropBelow(Level),
+}
+
+/// How the writer thread of [LogBufFile] writes the buffered lines.
+///
+/// All of them write the lines of one flush in a single syscall (unless it's larger than
+/// IOV_MAX lines for the vectored ones), so the appends of multiple processes don't interleave.
+/// To compare the throughput, run the benchmark with `make bench bench_buf_file_write_mode`.
+#[derive(Hash, Clone, Copy, PartialEq, Debug, Default)]
+pub enum WriteMode {
+    /// Copy the lines into one buffer, and write(2)
+    #[default]
+    Copy,
+    /// writev(2) over the lines without copy
+    Vectored,
+    /// Submit writev to io_uring, fallback to [WriteMode::Vectored] when io_uring is not
+    /// permitted (for example by seccomp in containers).
+    #[cfg(feature = "io_uring")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "io_uring")))]
+    IoUring,
+}
+
 /// Config for buffered file sink which merged I/O and delay flush.
 /// Optional log rotation can be configured.
 ///
@@ -48,9 +101,10 @@ const FLUSH_SIZE_DEFAULT: usize = 4096;
 /// ) -> Builder {
 ///     let format = LogFormat::new(time_fmt, format_func);
 ///     let _file_path = file_path.into();
-///     let p = path::absolute(&_file_path).expect("path convert to absolute");
-///     let dir = p.parent().unwrap();
-///     let file_name = Path::new(p.file_name().unwrap());
+///     let p = path::absolute(&_file_path).unwrap_or(_file_path);
+///     // An invalid path is reported by build()
+///     let dir = p.parent().unwrap_or(Path::new(""));
+///     let file_name = Path::new(p.file_name().unwrap_or_default());
 ///     let mut file = LogBufFile::new(dir, file_name, max_level, format, flush_millis);
 ///     if let Some(ro) = rotate {
 ///         file = file.rotation(ro);
@@ -81,6 +135,39 @@ pub struct LogBufFile {
     /// Auto flush when buffer size is reached, **default to be 4KB**,
     /// so that during reload or graceful restart, the line will not be break.
     pub flush_size: usize,
+
+    /// Permissions, ownership and flags of the file and dir
+    pub file_options: FileOptions,
+
+    /// How the writer thread writes the buffered lines, default to [WriteMode::Copy].
+    pub write_mode: WriteMode,
+
+    /// The number of lines waiting for the writer thread, **default to be 1024**.
+    pub queue_size: usize,
+
+    /// What to do when the queue is full, default to block.
+    pub backpressure: Backpressure,
+
+    /// Format the records in the writer thread instead of the logging thread, default to false.
+    pub deferred_format: bool,
+
+    /// Records at this level or more severe are written immediately, regardless of `flush_millis`
+    /// and `flush_size`, default to None.
+    pub flush_level: Option<Level>,
+
+    /// fdatasync after writing the records of `flush_level`, default to false.
+    pub flush_sync: bool,
+
+    /// When to fdatasync the file, default to [SyncPolicy::None]
+    pub sync_policy: SyncPolicy,
+
+    /// Check the free space of the disk, default to None
+    pub disk_guard: Option<DiskGuard>,
+
+    /// Interval to check whether the file is deleted or moved, default to None
+    pub auto_reopen: Option<Duration>,
+
+    created_dirs: CreatedDirs,
 }
 
 impl LogBufFile {
@@ -109,9 +196,7 @@ impl LogBufFile {
         P2: Into<PathBuf>,
     {
         let dir_path: PathBuf = dir.into();
-        if !dir_path.exists() {
-            std::fs::create_dir(&dir_path).expect("create dir for log");
-        }
+        let created_dirs = CreatedDirs::new(&dir_path);
         let file_path = dir_path.join(file_name.into()).into_boxed_path();
         Self {
             level,
@@ -120,6 +205,17 @@ impl LogBufFile {
             flush_millis,
             rotation: None,
             flush_size: FLUSH_SIZE_DEFAULT,
+            file_options: FileOptions::default(),
+            write_mode: WriteMode::Copy,
+            queue_size: QUEUE_SIZE_DEFAULT,
+            backpressure: Backpressure::Block,
+            deferred_format: false,
+            flush_level: None,
+            flush_sync: false,
+            sync_policy: SyncPolicy::None,
+            disk_guard: None,
+            auto_reopen: None,
+            created_dirs,
         }
     }
 
@@ -127,11 +223,89 @@ impl LogBufFile {
         self.rotation = Some(ro);
         self
     }
+
+    /// Set the permissions, ownership and flags of the file and dir.
+    pub fn file_options(mut self, opts: FileOptions) -> Self {
+        self.file_options = opts;
+        self
+    }
+
+    /// Set how the writer thread writes the buffered lines, see [WriteMode].
+    pub fn write_mode(mut self, mode: WriteMode) -> Self {
+        self.write_mode = mode;
+        self
+    }
+
+    /// Set the capacity of the queue to the writer thread, and what to do when it's full.
+    ///
+    /// # Example
+    ///
+    /// ``` rust
+    /// use captains_log::*;
+    /// let file = LogBufFile::new("/tmp", "test.log", Level::Info, recipe::LOG_FORMAT_DEBUG, 0)
+    ///     .backpressure(Backpressure::DropBelow(Level::Warn), 4096);
+    /// ```
+    pub fn backpressure(mut self, policy: Backpressure, queue_size: usize) -> Self {
+        self.backpressure = policy;
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Move the formatting off the logging threads.
+    ///
+    /// The record is captured (level, time, location, message and key-values rendered to string,
+    /// thread and scope), and formatted by the writer thread with [LogFormat].
+    /// It shortens the time spent in the logging threads, at the cost of more memory in the queue.
+    pub fn deferred_format(mut self) -> Self {
+        self.deferred_format = true;
+        self
+    }
+
+    /// Write the records at `level` or more severe immediately, so that they are not lost
+    /// in the buffer on a crash that is not a panic (SIGKILL, abort from C code).
+    ///
+    /// When `sync` is true, also fdatasync the file after writing these records, so that they
+    /// survive a power failure. Only needed when `flush_millis` is larger than zero.
+    ///
+    /// # Example
+    ///
+    /// ``` rust
+    /// use captains_log::*;
+    /// let file = LogBufFile::new("/tmp", "test.log", Level::Info, recipe::LOG_FORMAT_DEBUG, 1000)
+    ///     .flush_level(Level::Error, false);
+    /// ```
+    pub fn flush_level(mut self, level: Level, sync: bool) -> Self {
+        self.flush_level = Some(level);
+        self.flush_sync = sync;
+        self
+    }
+
+    /// Set the durability of the file, see [SyncPolicy].
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Act when the free space of the disk is low, see [DiskGuard].
+    pub fn disk_guard(mut self, guard: DiskGuard) -> Self {
+        self.disk_guard = Some(guard);
+        self
+    }
+
+    /// Check periodically whether the file is deleted or moved, and reopen it,
+    /// see [LogRawFile::auto_reopen()](crate::LogRawFile::auto_reopen()).
+    ///
+    /// The check is performed by the writer thread before writing the buffer.
+    pub fn auto_reopen(mut self, interval: Duration) -> Self {
+        self.auto_reopen = Some(interval);
+        self
+    }
 }
 
 impl SinkConfigBuild for LogBufFile {
-    fn build(&self) -> LogSink {
-        LogSink::BufFile(LogSinkBufFile::new(self))
+    fn build(&self, builder: &Builder) -> std::io::Result<LogSink> {
+        self.created_dirs.apply(&self.file_options)?;
+        Ok(LogSink::BufFile(LogSinkBufFile::new(self, builder)?))
     }
 }
 
@@ -154,13 +328,33 @@ pub(crate) struct LogSinkBufFile {
     max_level: Level,
     // raw fd only valid before original File close, use ArcSwap to prevent drop while using.
     formatter: LogFormat,
+    path: PathBuf,
+    file_options: FileOptions,
     _th: thread::JoinHandle<()>,
-    tx: MTx<mpsc::Array<Msg>>,
+    tx: MTx<mpmc::Array<Msg>>,
+    /// To drop the oldest line
+    rx: MRx<mpmc::Array<Msg>>,
+    backpressure: Backpressure,
+    /// Shared with the writer thread
+    metrics: Arc<SinkMetrics>,
+    deferred_format: bool,
+    /// Set by the writer thread
+    healthy: Arc<AtomicBool>,
+    /// Shared with the writer thread
+    spill: Arc<ArcSwapOption<LogSink>>,
 }
 
 impl LogSinkBufFile {
-    fn new(config: &LogBufFile) -> Self {
-        let (tx, rx) = mpsc::bounded_blocking(1024);
+    fn new(config: &LogBufFile, builder: &Builder) -> std::io::Result<Self> {
+        let clock = &builder.clock;
+        let mut queue_size = config.queue_size;
+        if queue_size == 0 {
+            queue_size = QUEUE_SIZE_DEFAULT;
+        }
+        let (tx, rx) = mpmc::bounded_blocking(queue_size);
+        let metrics = Arc::new(SinkMetrics::new(true));
+        let healthy = Arc::new(AtomicBool::new(true));
+        let spill = Arc::new(ArcSwapOption::empty());
 
         let mut flush_millis = config.flush_millis;
         if flush_millis == 0 || flush_millis > 1000 {
@@ -168,8 +362,9 @@ impl LogSinkBufFile {
         }
         let mut rotate_impl: Option<LogRotate> = None;
         if let Some(r) = &config.rotation {
-            rotate_impl = Some(r.build(&config.file_path));
+            rotate_impl = Some(r.build(&config.file_path, clock, &config.file_options, None)?);
         }
+        let path = config.file_path.to_path_buf();
         let mut flush_size = config.flush_size;
         if flush_size == 0 {
             flush_size = FLUSH_SIZE_DEFAULT;
@@ -177,21 +372,95 @@ impl LogSinkBufFile {
         let mut inner = BufFileInner {
             size: 0,
             create_time: None,
-            path: config.file_path.to_path_buf(),
+            path: path.clone(),
             f: None,
             flush_millis,
             flush_size,
-            buf: Vec::with_capacity(4096),
+            write_mode: config.write_mode,
+            buf: Vec::new(),
+            lines: Vec::new(),
+            buf_len: 0,
+            #[cfg(feature = "io_uring")]
+            uring: None,
             rotate: rotate_impl,
+            clock: clock.clone(),
+            file_options: config.file_options,
+            formatter: config.format.clone(),
+            metrics: metrics.clone(),
+            reported: 0,
+            fields: builder.fields.clone(),
+            flush_level: config.flush_level,
+            flush_sync: config.flush_sync,
+            sync: FileSync::new(config.sync_policy, &config.file_path, &builder.clock),
+            guard: config
+                .disk_guard
+                .map(|g| DiskGuardState::new(g, &config.file_path, spill.clone())),
+            healthy: healthy.clone(),
+            spill: spill.clone(),
+            watch: config.auto_reopen.map(PathWatch::new),
         };
-        let _th = thread::spawn(move || inner.log_writer(rx));
-        Self { max_level: config.level, formatter: config.format.clone(), tx, _th }
+        match inner.write_mode {
+            WriteMode::Copy => inner.buf.reserve(flush_size),
+            #[cfg(feature = "io_uring")]
+            WriteMode::IoUring => match UringWriter::new() {
+                Ok(uring) => inner.uring = Some(uring),
+                Err(e) => {
+                    // Fallback to writev
+                    report_error(SinkErrorKind::Open, &inner.path.to_string_lossy(), &e);
+                    inner.write_mode = WriteMode::Vectored;
+                }
+            },
+            _ => {}
+        }
+        let _rx = rx.clone();
+        let _th = thread::spawn(move || inner.log_writer(_rx));
+        Ok(Self {
+            max_level: config.level,
+            formatter: config.format.clone(),
+            path,
+            file_options: config.file_options,
+            tx,
+            rx,
+            backpressure: config.backpressure,
+            metrics,
+            deferred_format: config.deferred_format,
+            healthy,
+            spill,
+            _th,
+        })
+    }
+
+    #[inline]
+    fn drop_line(&self) {
+        self.metrics.on_drop();
+    }
+
+    fn send_line(&self, level: Level, msg: Msg) {
+        let block = match self.backpressure {
+            Backpressure::Block => true,
+            Backpressure::DropBelow(below) => level <= below,
+            _ => false,
+        };
+        if block {
+            let _ = self.tx.send(msg);
+            return;
+        }
+        if self.backpressure == Backpressure::DropOldest {
+            send_drop_oldest(&self.tx, &self.rx, msg, || self.drop_line());
+        } else if let Err(TrySendError::Full(_)) = self.tx.try_send(msg) {
+            self.drop_line();
+        }
     }
 }
 
 impl LogSinkTrait for LogSinkBufFile {
     #[inline]
     fn open(&self) -> std::io::Result<()> {
+        // The file is opened by the writer thread, check the error here.
+        if let Some(dir) = self.path.parent() {
+            self.file_options.create_dir(dir)?;
+        }
+        self.file_options.open(&self.path)?;
         self.reopen()
     }
     fn reopen(&self) -> std::io::Result<()> {
@@ -200,15 +469,49 @@ impl LogSinkTrait for LogSinkBufFile {
     }
 
     #[inline(always)]
-    fn log(&self, now: &Timer, r: &Record) {
+    fn log(&self, now: &Timer, ctx: &RecordContext, r: &Record) {
         if r.level() <= self.max_level {
+            self.metrics.on_accept();
             // Get a stable buffer,
             // for concurrently write to file from multi process.
-            let buf = self.formatter.process(now, r);
-            let _ = self.tx.send(Msg::Line(buf));
+            if self.deferred_format {
+                let record = DeferredRecord::capture(now, ctx, r);
+                self.send_line(r.level(), Msg::Record(Box::new(record)));
+            } else {
+                let buf = self.formatter.process(now, ctx, r);
+                self.send_line(r.level(), Msg::Line(r.level(), buf));
+            }
         }
     }
 
+    fn stats(&self, out: &mut Vec<SinkStats>) {
+        let name = self.path.display().to_string();
+        out.push(self.metrics.snapshot(name, self.tx.len() as u64));
+    }
+
+    #[inline(always)]
+    fn healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    fn write_formatted(&self, buf: &[u8]) {
+        let line = String::from_utf8_lossy(buf).into_owned();
+        self.send_line(Level::Info, Msg::Line(Level::Info, line));
+    }
+
+    fn set_spill(&self, sink: Arc<LogSink>) -> bool {
+        self.spill.store(Some(sink));
+        true
+    }
+
+    /// Reopen when the file is removed or failed to write, the result is known asynchronously.
+    fn probe(&self) -> bool {
+        if !self.healthy() || !self.path.exists() {
+            let _ = self.reopen();
+        }
+        self.healthy()
+    }
+
     #[inline(always)]
     fn flush(&self) {
         let o = Arc::new(Once::new());
@@ -218,27 +521,168 @@ impl LogSinkTrait for LogSinkBufFile {
     }
 }
 
+/// Send the line without blocking, evicting the oldest lines when the queue is full.
+///
+/// The control messages popped from the queue should not be lost, they are re-sent before the
+/// line without blocking either. (They might be reordered with the other controls in the queue,
+/// which does not matter.) When the queue is full of controls, the line is dropped instead.
+fn send_drop_oldest<F: Fn()>(
+    tx: &MTx<mpmc::Array<Msg>>, rx: &MRx<mpmc::Array<Msg>>, msg: Msg, drop_line: F,
+) {
+    let mut pending = VecDeque::with_capacity(1);
+    pending.push_back(msg);
+    let mut line_pending = true;
+    let mut controls_popped = 0;
+    while let Some(msg) = pending.pop_front() {
+        match tx.try_send(msg) {
+            Ok(_) => {}
+            Err(TrySendError::Full(msg)) => {
+                pending.push_front(msg);
+                match rx.try_recv() {
+                    Ok(Msg::Line(..)) | Ok(Msg::Record(_)) => drop_line(),
+                    Ok(control) => {
+                        controls_popped += 1;
+                        if line_pending {
+                            // The line is always the last one
+                            pending.insert(pending.len() - 1, control);
+                            if controls_popped > tx.len() {
+                                pending.pop_back();
+                                line_pending = false;
+                                drop_line();
+                            }
+                        } else {
+                            pending.push_back(control);
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => return,
+        }
+    }
+}
+
 enum Msg {
-    Line(String),
+    Line(Level, String),
+    Record(Box<DeferredRecord>),
     Reopen,
     Flush(Arc<Once>),
 }
 
+/// A record captured by the logging thread, to be formatted by the writer thread.
+struct DeferredRecord {
+    time: DateTime<Local>,
+    seq: u64,
+    level: Level,
+    target: String,
+    module_path: Option<Cow<'static, str>>,
+    file: Option<Cow<'static, str>>,
+    line: Option<u32>,
+    msg: Cow<'static, str>,
+    kvs: Vec<(String, String)>,
+    context: ThreadContext,
+}
+
+struct KvCollector(Vec<(String, String)>);
+
+impl<'kvs> kv::VisitSource<'kvs> for KvCollector {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+impl DeferredRecord {
+    #[inline]
+    fn capture(now: &Timer, ctx: &RecordContext, r: &Record) -> Self {
+        let msg = match r.args().as_str() {
+            Some(s) => Cow::Borrowed(s),
+            None => Cow::Owned(r.args().to_string()),
+        };
+        let module_path = match r.module_path_static() {
+            Some(s) => Some(Cow::Borrowed(s)),
+            None => r.module_path().map(|s| Cow::Owned(s.to_string())),
+        };
+        let file = match r.file_static() {
+            Some(s) => Some(Cow::Borrowed(s)),
+            None => r.file().map(|s| Cow::Owned(s.to_string())),
+        };
+        let mut kvs = KvCollector(Vec::new());
+        let _ = r.key_values().visit(&mut kvs);
+        Self {
+            time: **now,
+            seq: ctx.seq(),
+            level: r.level(),
+            target: r.target().to_string(),
+            module_path,
+            file,
+            line: r.line(),
+            msg,
+            kvs: kvs.0,
+            context: ThreadContext::capture(),
+        }
+    }
+
+    fn format(&self, formatter: &LogFormat, fields: &[(String, String)]) -> String {
+        let now = Timer::deferred(self.time);
+        formatter.process(
+            &now,
+            &RecordContext::deferred(self.seq, fields, &self.context),
+            &Record::builder()
+                .level(self.level)
+                .target(&self.target)
+                .module_path(self.module_path.as_deref())
+                .file(self.file.as_deref())
+                .line(self.line)
+                .args(format_args!("{}", self.msg))
+                .key_values(&self.kvs)
+                .build(),
+        )
+    }
+}
+
 struct BufFileInner {
     size: u64,
     create_time: Option<SystemTime>,
     path: PathBuf,
     f: Option<std::fs::File>,
+    write_mode: WriteMode,
+    /// The pending lines for WriteMode::Copy
     buf: Vec<u8>,
+    /// The pending lines for vectored writes
+    lines: Vec<Vec<u8>>,
+    /// The total size of pending lines
+    buf_len: usize,
+    #[cfg(feature = "io_uring")]
+    uring: Option<UringWriter>,
     flush_millis: usize,
     rotate: Option<LogRotate>,
     flush_size: usize,
+    clock: Clock,
+    file_options: FileOptions,
+    formatter: LogFormat,
+    /// For formatting the deferred records
+    fields: Vec<(String, String)>,
+    /// Shared with LogSinkBufFile
+    metrics: Arc<SinkMetrics>,
+    /// The number of dropped lines written to the file
+    reported: u64,
+    flush_level: Option<Level>,
+    flush_sync: bool,
+    sync: FileSync,
+    guard: Option<DiskGuardState>,
+    /// Shared with LogSinkBufFile
+    healthy: Arc<AtomicBool>,
+    /// The sink to take over the lines failed to write, shared with LogSinkBufFile
+    spill: Arc<ArcSwapOption<LogSink>>,
+    watch: Option<PathWatch>,
 }
 
 impl FileSinkTrait for BufFileInner {
     #[inline(always)]
     fn get_create_time(&self) -> SystemTime {
-        self.create_time.unwrap()
+        // None when failed to open the file
+        self.create_time.unwrap_or_else(|| self.clock.now())
     }
 
     #[inline(always)]
@@ -249,63 +693,172 @@ impl FileSinkTrait for BufFileInner {
 
 impl BufFileInner {
     fn reopen(&mut self) {
-        match open_file(&self.path) {
+        match self.file_options.open(&self.path) {
             Ok(f) => {
-                let mt = metadata(&self.path).expect("get metadata");
+                let mt = match f.metadata() {
+                    Ok(mt) => mt,
+                    Err(e) => {
+                        report_error(SinkErrorKind::Stat, &self.path.to_string_lossy(), &e);
+                        return;
+                    }
+                };
                 self.size = mt.len();
-                if self.create_time.is_none() {
-                    // NOTE Posix has no create_time, so use mtime. rotation will delay a cycle after program restart.
-                    self.create_time = Some(mt.modified().unwrap());
+                match self.create_time {
+                    None => {
+                        self.create_time = Some(file_create_time(&f, &mt, self.clock.now()));
+                    }
+                    Some(t) if self.size == 0 => record_create_time(&f, &mt, t),
+                    _ => {}
                 }
                 self.f.replace(f);
+                self.healthy.store(true, Ordering::Release);
             }
             Err(e) => {
-                eprintln!("open logfile {:#?} failed: {:?}", &self.path, e);
+                report_error(SinkErrorKind::Open, &self.path.to_string_lossy(), &e);
+                self.healthy.store(false, Ordering::Release);
             }
         }
     }
 
     fn write(&mut self, mut s: Vec<u8>) {
-        if self.buf.len() + s.len() > self.flush_size && !self.buf.is_empty() {
+        if self.buf_len + s.len() > self.flush_size && self.buf_len > 0 {
             self.flush(false);
         }
-        self.buf.reserve(s.len());
-        self.buf.append(&mut s);
-        if self.buf.len() >= self.flush_size {
+        self.buf_len += s.len();
+        if self.write_mode == WriteMode::Copy {
+            self.buf.reserve(s.len());
+            self.buf.append(&mut s);
+        } else {
+            self.lines.push(s);
+        }
+        if self.buf_len >= self.flush_size {
             self.flush(false);
         }
     }
 
+    /// Check the free space, and write the notice when the guard engages or releases.
+    fn check_guard(&mut self, force: bool) {
+        if let Some(guard) = self.guard.as_ref() {
+            if let Some(msg) = guard.check(self.rotate.as_ref(), force) {
+                let buf = self.formatter.process_notice(
+                    &self.clock,
+                    Level::Warn,
+                    format_args!("{}", msg),
+                );
+                if guard.route(Level::Warn) == GuardRoute::Fallback {
+                    guard.write_fallback(&buf);
+                } else {
+                    self.write(buf.into());
+                }
+            }
+        }
+    }
+
+    /// Returns true if the line should be written to the file.
+    #[inline(always)]
+    fn check_guard_route(&mut self, level: Level, line: &str) -> bool {
+        if self.guard.is_none() {
+            return true;
+        }
+        self.check_guard(false);
+        let guard = self.guard.as_ref().unwrap();
+        match guard.route(level) {
+            GuardRoute::Write => true,
+            GuardRoute::Drop => false,
+            GuardRoute::Fallback => {
+                guard.write_fallback(line);
+                false
+            }
+        }
+    }
+
+    /// Flush immediately for the records of `flush_level`, or [SyncPolicy::PerRecord]
+    #[inline(always)]
+    fn check_flush_level(&mut self, level: Level) {
+        if self.sync.policy == SyncPolicy::PerRecord {
+            self.flush(false);
+            return;
+        }
+        if let Some(flush_level) = self.flush_level {
+            if level <= flush_level {
+                self.flush(false);
+                if self.flush_sync {
+                    if let Some(f) = self.f.as_ref() {
+                        self.sync.sync(f);
+                    }
+                }
+            }
+        }
+    }
+
     #[inline(always)]
     fn check_rotate(&mut self) {
         if let Some(ro) = self.rotate.as_ref() {
             if ro.rotate(self) {
+                self.metrics.on_rotate();
+                self.create_time = Some(self.clock.now());
                 self.reopen();
             }
         }
     }
 
     fn flush(&mut self, wait_rotate: bool) {
+        if let (Some(watch), Some(f)) = (self.watch.as_ref(), self.f.as_ref()) {
+            if watch.check(&self.path, f) {
+                self.reopen();
+            }
+        }
+        if self.f.is_some() && self.buf_len > 0 {
+            // In case of the time crossing the boundary of rotation by age.
+            self.check_rotate();
+        }
         if let Some(f) = self.f.as_ref() {
-            self.size += self.buf.len() as u64;
+            self.size += self.buf_len as u64;
+            let fd = f.as_raw_fd() as libc::c_int;
+            let start = self.metrics.start();
             // Use unbuffered I/O to ensure the write ok
-            let mut p = self.buf.as_ptr();
-            let mut l = self.buf.len();
-            loop {
-                let r = unsafe {
-                    libc::write(f.as_raw_fd() as libc::c_int, p as *const libc::c_void, l)
-                };
-                if r == l as isize || r < 0 {
-                    // Ignore write error (disk err, space err), should not affect the program
-                    break;
+            let r = match self.write_mode {
+                WriteMode::Copy => write_fd(fd, &self.buf),
+                WriteMode::Vectored => write_lines(&self.lines, |iov| {
+                    let r = unsafe { libc::writev(fd, iov.as_ptr(), iov.len() as libc::c_int) };
+                    if r < 0 {
+                        -std::io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)
+                            as isize
+                    } else {
+                        r
+                    }
+                }),
+                #[cfg(feature = "io_uring")]
+                WriteMode::IoUring => {
+                    let uring = self.uring.as_mut().unwrap();
+                    write_lines(&self.lines, |iov| uring.writev(fd, iov))
+                }
+            };
+            if self.buf_len > 0 {
+                match r {
+                    Ok(_) => {
+                        self.metrics.on_write(self.buf_len, start);
+                        self.healthy.store(true, Ordering::Release);
+                    }
+                    Err(e) => {
+                        self.metrics.on_error();
+                        if self.healthy.swap(false, Ordering::AcqRel) {
+                            report_error(SinkErrorKind::Write, &self.path.to_string_lossy(), &e);
+                        }
+                        self.spill_pending();
+                    }
                 }
-                // NOTE: If early return happens, means you are using a filesystem not
-                // supporting atomic append
-                l -= r as usize;
-                p = unsafe { p.add(r as usize) };
+                self.sync.on_write(f, true);
             }
-            unsafe { self.buf.set_len(0) };
+            self.buf.clear();
+            self.lines.clear();
+            self.buf_len = 0;
             self.check_rotate();
+        } else if self.buf_len > 0 && self.spill_pending() {
+            // Failed to open the file
+            self.buf.clear();
+            self.lines.clear();
+            self.buf_len = 0;
         }
         if wait_rotate {
             if let Some(ro) = self.rotate.as_ref() {
@@ -314,21 +867,70 @@ impl BufFileInner {
         }
     }
 
-    fn log_writer(&mut self, rx: Rx<mpsc::Array<Msg>>) {
+    /// Hand over the pending lines to the spill sink (the secondary of LogFailover), returns false
+    /// if there's none.
+    fn spill_pending(&self) -> bool {
+        let spill = self.spill.load();
+        let sink = match spill.as_ref() {
+            Some(sink) => sink,
+            None => return false,
+        };
+        if self.write_mode == WriteMode::Copy {
+            sink.write_formatted(&self.buf);
+        } else {
+            for line in self.lines.iter() {
+                sink.write_formatted(line);
+            }
+        }
+        true
+    }
+
+    /// Write a warning when lines are dropped since the last report, called when queue drains.
+    fn report_dropped(&mut self) {
+        let dropped = self.metrics.dropped();
+        if dropped == self.reported {
+            return;
+        }
+        let n = dropped - self.reported;
+        self.reported = dropped;
+        let buf = self.formatter.process_notice(
+            &self.clock,
+            Level::Warn,
+            format_args!("{} lines dropped because the queue of log is full", n),
+        );
+        self.write(buf.into());
+    }
+
+    fn log_writer(&mut self, rx: MRx<mpmc::Array<Msg>>) {
         self.reopen();
         self.check_rotate();
+        self.check_guard(true);
 
         macro_rules! process {
             ($msg: expr) => {
                 match $msg {
-                    Msg::Line(line) => {
-                        self.write(line.into());
+                    Msg::Line(level, line) => {
+                        if self.check_guard_route(level, &line) {
+                            self.write(line.into());
+                            self.check_flush_level(level);
+                        }
+                    }
+                    Msg::Record(record) => {
+                        let buf = record.format(&self.formatter, &self.fields);
+                        if self.check_guard_route(record.level, &buf) {
+                            self.write(buf.into());
+                            self.check_flush_level(record.level);
+                        }
                     }
                     Msg::Reopen => {
                         self.reopen();
                     }
                     Msg::Flush(o) => {
+                        self.report_dropped();
                         self.flush(true);
+                        if let Some(f) = self.f.as_ref() {
+                            self.sync.on_flush(f);
+                        }
                         o.call_once(|| {});
                     }
                 }
@@ -342,9 +944,13 @@ impl BufFileInner {
                         while let Ok(msg) = rx.try_recv() {
                             process!(msg);
                         }
+                        self.report_dropped();
                     }
                     Err(RecvTimeoutError::Timeout) => {
                         self.flush(false);
+                        if let Some(f) = self.f.as_ref() {
+                            self.sync.on_idle(f);
+                        }
                     }
                     Err(RecvTimeoutError::Disconnected) => {
                         self.flush(true);
@@ -360,6 +966,7 @@ impl BufFileInner {
                         while let Ok(msg) = rx.try_recv() {
                             process!(msg);
                         }
+                        self.report_dropped();
                         self.flush(false);
                     }
                     Err(_) => {
@@ -371,3 +978,192 @@ impl BufFileInner {
         }
     }
 }
+
+/// Write all the lines with the `writev` function, which returns the bytes written or
+/// negative errno on error.
+fn write_lines<F>(lines: &[Vec<u8>], mut writev: F) -> std::io::Result<()>
+where
+    F: FnMut(&[libc::iovec]) -> isize,
+{
+    let mut iov: Vec<libc::iovec> = lines
+        .iter()
+        .map(|line| libc::iovec {
+            iov_base: line.as_ptr() as *mut libc::c_void,
+            iov_len: line.len(),
+        })
+        .collect();
+    let mut start = 0;
+    while start < iov.len() {
+        let end = iov.len().min(start + libc::UIO_MAXIOV as usize);
+        let r = writev(&iov[start..end]);
+        if r < 0 {
+            // Do not panic on write error (disk err, space err), should not affect the program
+            return Err(std::io::Error::from_raw_os_error(-r as i32));
+        } else if r == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        // NOTE: If partial write happens, means you are using a filesystem not
+        // supporting atomic append
+        let mut r = r as usize;
+        while r > 0 && start < iov.len() {
+            let v = &mut iov[start];
+            if r >= v.iov_len {
+                r -= v.iov_len;
+                start += 1;
+            } else {
+                v.iov_base = unsafe { (v.iov_base as *mut u8).add(r) } as *mut libc::c_void;
+                v.iov_len -= r;
+                r = 0;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Submit writev to io_uring, and wait for the completion.
+#[cfg(feature = "io_uring")]
+struct UringWriter {
+    ring: io_uring::IoUring,
+}
+
+#[cfg(feature = "io_uring")]
+impl UringWriter {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self { ring: io_uring::IoUring::new(4)? })
+    }
+
+    fn writev(&mut self, fd: libc::c_int, iov: &[libc::iovec]) -> isize {
+        use io_uring::{opcode, types};
+        // offset -1 to write at the current position, which respects O_APPEND
+        let entry = opcode::Writev::new(types::Fd(fd), iov.as_ptr(), iov.len() as u32)
+            .offset(u64::MAX)
+            .build();
+        // The iovec is valid until the completion, as we wait for it.
+        if unsafe { self.ring.submission().push(&entry) }.is_err() {
+            return -libc::EBUSY as isize;
+        }
+        if let Err(e) = self.ring.submit_and_wait(1) {
+            return -e.raw_os_error().unwrap_or(libc::EIO) as isize;
+        }
+        match self.ring.completion().next() {
+            Some(cqe) => cqe.result() as isize,
+            None => -libc::EIO as isize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn recv_all(rx: &MRx<mpmc::Array<Msg>>) -> Vec<String> {
+        let mut msgs = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            msgs.push(match msg {
+                Msg::Line(_, line) => line,
+                Msg::Record(r) => r.msg.to_string(),
+                Msg::Reopen => "reopen".to_string(),
+                Msg::Flush(_) => "flush".to_string(),
+            });
+        }
+        msgs
+    }
+
+    #[test]
+    fn test_send_drop_oldest() {
+        let (tx, rx) = mpmc::bounded_blocking::<Msg>(3);
+        let line = |s: &str| Msg::Line(Level::Info, s.to_string());
+        let dropped = Cell::new(0);
+        let drop_line = || dropped.set(dropped.get() + 1);
+        tx.send(Msg::Reopen).unwrap();
+        tx.send(line("a")).unwrap();
+        tx.send(Msg::Flush(Arc::new(Once::new()))).unwrap();
+        // The controls are kept before the line, "a" is dropped
+        send_drop_oldest(&tx, &rx, line("b"), drop_line);
+        assert_eq!(dropped.get(), 1);
+        let mut msgs = recv_all(&rx);
+        assert_eq!(msgs.pop().unwrap(), "b");
+        msgs.sort();
+        assert_eq!(msgs, ["flush", "reopen"]);
+
+        // The queue is full of controls, the line is dropped without blocking
+        for _ in 0..3 {
+            tx.send(Msg::Reopen).unwrap();
+        }
+        send_drop_oldest(&tx, &rx, line("c"), drop_line);
+        assert_eq!(dropped.get(), 2);
+        assert_eq!(recv_all(&rx), ["reopen", "reopen", "reopen"]);
+    }
+
+    /// Collect the bytes of iov like writev(2), writing at most `limit` bytes
+    fn fake_writev(out: &mut Vec<u8>, iov: &[libc::iovec], limit: usize) -> isize {
+        let mut n = 0;
+        for v in iov {
+            let len = v.iov_len.min(limit - n);
+            out.extend_from_slice(unsafe {
+                std::slice::from_raw_parts(v.iov_base as *const u8, len)
+            });
+            n += len;
+        }
+        n as isize
+    }
+
+    #[test]
+    fn test_write_lines() {
+        let max = libc::UIO_MAXIOV as usize;
+        let lines: Vec<Vec<u8>> =
+            (0..max + 10).map(|i| format!("line {}\n", i).into_bytes()).collect();
+        let expected = lines.concat();
+
+        // Split at UIO_MAXIOV
+        let mut out = Vec::new();
+        let mut calls = Vec::new();
+        write_lines(&lines, |iov| {
+            calls.push(iov.len());
+            fake_writev(&mut out, iov, usize::MAX)
+        })
+        .expect("write");
+        assert_eq!(calls, [max, 10]);
+        assert_eq!(out, expected);
+
+        // Partial writes resume in the middle of the line
+        let mut out = Vec::new();
+        let mut calls = 0;
+        write_lines(&lines, |iov| {
+            calls += 1;
+            fake_writev(&mut out, iov, 7)
+        })
+        .expect("write");
+        assert!(calls > lines.len());
+        assert_eq!(out, expected);
+
+        // Stop on error
+        let mut calls = 0;
+        let e = write_lines(&lines, |_| {
+            calls += 1;
+            -libc::ENOSPC as isize
+        })
+        .unwrap_err();
+        assert_eq!(calls, 1);
+        assert_eq!(e.raw_os_error(), Some(libc::ENOSPC));
+
+        let mut out = Vec::new();
+        let mut calls = 0;
+        let e = write_lines(&lines, |iov| {
+            calls += 1;
+            if calls == 1 {
+                fake_writev(&mut out, iov, 10)
+            } else {
+                -libc::EIO as isize
+            }
+        })
+        .unwrap_err();
+        assert_eq!(calls, 2);
+        assert_eq!(e.raw_os_error(), Some(libc::EIO));
+        assert_eq!(out, expected[..10]);
+
+        let e = write_lines(&lines, |_| 0).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::WriteZero);
+    }
+}
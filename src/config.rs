@@ -1,8 +1,10 @@
 use crate::log_impl::setup_log;
 use crate::{
-    formatter::{FormatRecord, TimeFormatter},
+    formatter::{FormatRecord, RecordContext, TimeFormatter},
     log_impl::{GlobalLogger, LogSink, LogSinkTrait},
-    time::Timer,
+    metrics::MetricsExport,
+    report::{report_error, ErrorHandler, SinkError, SinkErrorKind},
+    time::{Clock, TimeZone, Timer},
 };
 use log::{Level, LevelFilter, Record};
 use std::hash::{DefaultHasher, Hash, Hasher};
@@ -40,6 +42,24 @@ pub struct Builder {
     /// Different types of log sink
     pub sinks: Vec<Box<dyn SinkConfigTrait>>,
 
+    /// The source of time for log records and age-based rotation, default to system clock.
+    pub clock: Clock,
+
+    /// Static key-values attached to every record, see [Builder::with_field()]
+    pub fields: Vec<(String, String)>,
+
+    /// Receive the errors of log sinks instead of printing to stderr, see [Builder::on_error()]
+    pub error_handler: Option<ErrorHandler>,
+
+    /// Do not print the informational messages (like "log sinks opened") to stdout
+    pub quiet: bool,
+
+    /// Export the metrics of sinks periodically, see [Builder::export_metrics()]
+    pub metrics_export: Option<MetricsExport>,
+
+    /// Observe the write latency of all sinks, see [Builder::metrics_latency()]
+    pub metrics_latency: bool,
+
     /// subscribe to tracing as global dispatcher
     #[cfg(feature = "tracing")]
     #[cfg_attr(docsrs, doc(cfg(feature = "tracing")))]
@@ -97,6 +117,96 @@ impl Builder {
         self
     }
 
+    /// Replace the system clock for log records and age-based rotation, for deterministic tests.
+    /// See [Clock] for details.
+    #[inline]
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Attach a static key-value (service name, version, region...) to every record,
+    /// can be called multiple times. Setting the same key again will replace the value.
+    ///
+    /// Available to formatter by [FormatRecord::field()], and appended to syslog messages as
+    /// ` key=value`. [recipe::json_format_f()](crate::recipe::json_format_f()) nests them under
+    /// `"fields"`, so the keys like `level` do not collide.
+    ///
+    /// # Example
+    /// ```
+    /// use captains_log::*;
+    /// recipe::raw_file_logger_custom("/tmp/log_fields.log", Level::Info,
+    ///         recipe::RFC3339_TIME, recipe::json_format_f)
+    ///     .with_field("service", "billing")
+    ///     .with_field("version", env!("CARGO_PKG_VERSION"))
+    ///     .test().build().expect("setup log");
+    /// info!("started");
+    /// ```
+    pub fn with_field<K: Into<String>, V: ToString>(mut self, key: K, value: V) -> Self {
+        let key = key.into();
+        let value = value.to_string();
+        if let Some(f) = self.fields.iter_mut().find(|(k, _)| *k == key) {
+            f.1 = value;
+        } else {
+            self.fields.push((key, value));
+        }
+        self
+    }
+
+    /// Receive the errors of log sinks (open, write, rotate, connect...) instead of printing to
+    /// stderr, for example to count them in metrics. The handler is called on the thread where
+    /// the error occurred, which might be the background thread of the sink.
+    ///
+    /// Write errors are reported once until the sink recovers, see [GlobalLogger::health()].
+    ///
+    /// # Example
+    /// ```
+    /// use captains_log::*;
+    /// recipe::raw_file_logger("/tmp/log_on_error.log", Level::Info)
+    ///     .on_error(|e: &SinkError| {
+    ///         let _ = (e.kind, e.sink, e.error);
+    ///     })
+    ///     .quiet()
+    ///     .test().build().expect("setup log");
+    /// ```
+    pub fn on_error<F: Fn(&SinkError) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.error_handler = Some(std::sync::Arc::new(f));
+        self
+    }
+
+    /// Do not print the informational messages (like "log sinks opened") to stdout,
+    /// for CLI tools.
+    #[inline]
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Export [GlobalLogger::metrics()] in Prometheus text format periodically, to a file or
+    /// callback. The background thread is started on the first setup, and the export is replaced
+    /// on each setup.
+    #[inline]
+    pub fn export_metrics(mut self, export: MetricsExport) -> Self {
+        self.metrics_export = Some(export);
+        self
+    }
+
+    /// Observe the write latency of all sinks in [SinkStats::flush_latency](crate::SinkStats),
+    /// which costs a clock read for each record written by LogRawFile, LogConsole and Syslog.
+    /// By default only the batched writes of LogBufFile are timed.
+    ///
+    /// Implied by [Builder::export_metrics()].
+    #[inline]
+    pub fn metrics_latency(mut self) -> Self {
+        self.metrics_latency = true;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn latency_enabled(&self) -> bool {
+        self.metrics_latency || self.metrics_export.is_some()
+    }
+
     /// Add different types of log sink config, can be called multiple times.
     #[inline]
     pub fn add_sink<S: SinkConfigTrait>(mut self, config: S) -> Self {
@@ -125,6 +235,9 @@ impl Builder {
         self.rotation_signals.hash(&mut hasher);
         self.panic_hook.hash(&mut hasher);
         self.force_abort_on_panic.hash(&mut hasher);
+        self.clock.hash(&mut hasher);
+        self.fields.hash(&mut hasher);
+        self.latency_enabled().hash(&mut hasher);
         for sink in &self.sinks {
             sink.write_hash(&mut hasher);
         }
@@ -135,9 +248,15 @@ impl Builder {
     pub(crate) fn build_sinks(&self) -> std::io::Result<Vec<LogSink>> {
         let mut sinks = Vec::new();
         for config in &self.sinks {
-            let logger_sink = config.build();
+            let logger_sink = match config.build(self) {
+                Ok(sink) => sink,
+                Err(e) => {
+                    report_error(SinkErrorKind::Open, &sink_name(config.as_ref()), &e);
+                    return Err(e);
+                }
+            };
             if let Err(e) = logger_sink.open() {
-                eprintln!("failed to open log sink: {:?}", e);
+                report_error(SinkErrorKind::Open, &sink_name(config.as_ref()), &e);
                 return Err(e);
             }
             sinks.push(logger_sink);
@@ -156,9 +275,14 @@ impl Builder {
     }
 }
 
+/// The file path of sink config for reporting error
+fn sink_name(config: &dyn SinkConfigTrait) -> String {
+    config.get_file_path().map(|p| p.display().to_string()).unwrap_or_default()
+}
+
 pub(crate) trait SinkConfigBuild {
     /// Build an actual sink from config
-    fn build(&self) -> LogSink;
+    fn build(&self, builder: &Builder) -> std::io::Result<LogSink>;
 }
 
 #[allow(private_bounds)]
@@ -179,6 +303,7 @@ pub type FormatFunc = fn(FormatRecord) -> String;
 pub struct LogFormat {
     time_fmt: &'static str,
     format_fn: FormatFunc,
+    tz: TimeZone,
 }
 
 impl LogFormat {
@@ -204,13 +329,45 @@ impl LogFormat {
     /// let log_sink = LogRawFile::new("/tmp", "test.log", log::Level::Info, log_format);
     /// ```
     pub const fn new(time_fmt: &'static str, format_fn: FormatFunc) -> Self {
-        Self { time_fmt, format_fn }
+        Self { time_fmt, format_fn, tz: TimeZone::Local }
+    }
+
+    /// Display the time in the timezone, default to [TimeZone::Local].
+    ///
+    /// # Example
+    /// ```
+    /// use captains_log::{recipe, LogFormat, TimeZone};
+    /// const LOG_FORMAT_UTC: LogFormat = recipe::LOG_FORMAT_DEBUG.timezone(TimeZone::Utc);
+    /// ```
+    pub const fn timezone(mut self, tz: TimeZone) -> Self {
+        self.tz = tz;
+        self
     }
 
     #[inline(always)]
-    pub(crate) fn process(&self, now: &Timer, record: &Record) -> String {
-        let time = TimeFormatter { now, fmt_str: self.time_fmt };
-        let r = FormatRecord { record, time };
+    pub(crate) fn process(&self, now: &Timer, ctx: &RecordContext, record: &Record) -> String {
+        let time = TimeFormatter { now, fmt_str: self.time_fmt, tz: self.tz };
+        let r = FormatRecord { record, time, ctx };
         return (self.format_fn)(r);
     }
+
+    /// Format a notice from the sink itself, with target "captains_log".
+    #[track_caller]
+    pub(crate) fn process_notice(
+        &self, clock: &Clock, level: Level, args: std::fmt::Arguments,
+    ) -> String {
+        let loc = std::panic::Location::caller();
+        let now = Timer::new(clock);
+        self.process(
+            &now,
+            &RecordContext::new(&[]),
+            &Record::builder()
+                .level(level)
+                .target("captains_log")
+                .file_static(Some(loc.file()))
+                .line(Some(loc.line()))
+                .args(args)
+                .build(),
+        )
+    }
 }
@@ -1,7 +1,9 @@
 use crate::{
-    config::{LogFormat, SinkConfigBuild, SinkConfigTrait},
+    config::{Builder, LogFormat, SinkConfigBuild, SinkConfigTrait},
     env::EnvVarDefault,
+    formatter::RecordContext,
     log_impl::{LogSink, LogSinkTrait},
+    metrics::{SinkMetrics, SinkStats},
     time::Timer,
 };
 use log::{Level, Record};
@@ -69,8 +71,8 @@ impl FromStr for ConsoleTarget {
 crate::impl_from_env!(ConsoleTarget);
 
 impl SinkConfigBuild for LogConsole {
-    fn build(&self) -> LogSink {
-        LogSink::Console(LogSinkConsole::new(self))
+    fn build(&self, builder: &Builder) -> std::io::Result<LogSink> {
+        Ok(LogSink::Console(LogSinkConsole::new(self, builder.latency_enabled())))
     }
 }
 
@@ -93,14 +95,16 @@ pub(crate) struct LogSinkConsole {
     target_fd: libc::c_int,
     max_level: Level,
     formatter: LogFormat,
+    metrics: SinkMetrics,
 }
 
 impl LogSinkConsole {
-    fn new(config: &LogConsole) -> Self {
+    fn new(config: &LogConsole, timed: bool) -> Self {
         Self {
             target_fd: config.target as i32,
             max_level: config.level,
             formatter: config.format.clone(),
+            metrics: SinkMetrics::new(timed),
         }
     }
 }
@@ -115,15 +119,30 @@ impl LogSinkTrait for LogSinkConsole {
     }
 
     #[inline(always)]
-    fn log(&self, now: &Timer, r: &Record) {
+    fn log(&self, now: &Timer, ctx: &RecordContext, r: &Record) {
         if r.level() <= self.max_level {
-            let buf = self.formatter.process(now, r);
-            unsafe {
-                let _ = libc::write(self.target_fd, buf.as_ptr() as *const libc::c_void, buf.len());
-            }
+            self.metrics.on_accept();
+            let buf = self.formatter.process(now, ctx, r);
+            self.write_formatted(buf.as_bytes());
         }
     }
 
+    fn write_formatted(&self, buf: &[u8]) {
+        let start = self.metrics.start();
+        let n =
+            unsafe { libc::write(self.target_fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n < 0 {
+            self.metrics.on_error();
+        } else {
+            self.metrics.on_write(n as usize, start);
+        }
+    }
+
+    fn stats(&self, out: &mut Vec<SinkStats>) {
+        let name = if self.target_fd == ConsoleTarget::Stderr as i32 { "stderr" } else { "stdout" };
+        out.push(self.metrics.snapshot(name.to_string(), 0));
+    }
+
     #[inline(always)]
     fn flush(&self) {}
 }
@@ -0,0 +1,164 @@
+use crate::{
+    file_impl::Ticker,
+    log_impl::{LogSink, LogSinkTrait},
+    report::{report_error, SinkErrorKind},
+    rotation::LogRotate,
+};
+use arc_swap::ArcSwapOption;
+use log::Level;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+/// Check the free space of the volume periodically with `statvfs`, and act when it's below
+/// the threshold, configured by [LogRawFile::disk_guard()](crate::LogRawFile::disk_guard()) and
+/// [LogBufFile::disk_guard()](crate::LogBufFile::disk_guard()).
+///
+/// A warning record is written when the guard engages and releases.
+///
+/// # Example
+///
+/// ``` rust
+/// use captains_log::*;
+/// let guard = DiskGuard::new(1024 * 1024 * 1024, DiskAction::DropBelow(Level::Warn));
+/// let file = LogRawFile::new("/tmp", "test.log", Level::Info, recipe::LOG_FORMAT_DEBUG)
+///     .disk_guard(guard);
+/// ```
+#[derive(Hash, Clone, Copy, PartialEq, Debug)]
+pub struct DiskGuard {
+    /// Engage when the bytes available to unprivileged user is below this.
+    pub min_free: u64,
+    /// Interval to check the free space, default to 1 sec.
+    pub interval: Duration,
+    pub action: DiskAction,
+}
+
+/// What to do when the free space is below [DiskGuard::min_free]
+#[derive(Hash, Clone, Copy, PartialEq, Debug)]
+pub enum DiskAction {
+    /// Drop the records less severe than the level, for example Info and Debug
+    /// for `DropBelow(Level::Warn)`.
+    DropBelow(Level),
+    /// Delete the oldest archives of [Rotation](crate::rotation::Rotation) ahead of the upkeep,
+    /// until the free space is above the threshold.
+    PurgeArchives,
+    /// Stop writing the file, write to the secondary sink instead when wrapped by
+    /// [LogFailover](crate::LogFailover), otherwise to stderr.
+    Fallback,
+}
+
+impl DiskGuard {
+    pub fn new(min_free: u64, action: DiskAction) -> Self {
+        Self { min_free, interval: Duration::from_secs(1), action }
+    }
+
+    /// Set the interval to check free space.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+/// Where the record goes when checked by the guard
+#[derive(PartialEq, Debug)]
+pub(crate) enum GuardRoute {
+    Write,
+    Drop,
+    Fallback,
+}
+
+pub(crate) struct DiskGuardState {
+    config: DiskGuard,
+    /// The dir of the log file
+    dir: PathBuf,
+    last_check: Ticker,
+    engaged: AtomicBool,
+    /// The sink for DiskAction::Fallback, set by LogFailover
+    fallback: Arc<ArcSwapOption<LogSink>>,
+}
+
+impl DiskGuardState {
+    pub(crate) fn new(
+        config: DiskGuard, file_path: &Path, fallback: Arc<ArcSwapOption<LogSink>>,
+    ) -> Self {
+        let dir = file_path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        Self { config, dir, last_check: Ticker::new(), engaged: AtomicBool::new(false), fallback }
+    }
+
+    #[inline]
+    pub(crate) fn set_fallback(&self, sink: Arc<LogSink>) {
+        self.fallback.store(Some(sink));
+    }
+
+    /// Write the line routed by DiskAction::Fallback
+    pub(crate) fn write_fallback(&self, line: &str) {
+        match self.fallback.load().as_ref() {
+            Some(sink) => sink.write_formatted(line.as_bytes()),
+            None => eprint!("{}", line),
+        }
+    }
+
+    /// Whether the interval has elapsed since last check.
+    #[inline(always)]
+    pub(crate) fn due(&self) -> bool {
+        self.last_check.due(self.config.interval)
+    }
+
+    /// Check the free space when the interval has elapsed (or `force`).
+    ///
+    /// Returns the message to write when the guard engages or releases.
+    /// Should be called with the lock of the sink held, for purging the archives.
+    pub(crate) fn check(&self, rotate: Option<&LogRotate>, force: bool) -> Option<String> {
+        if !force && !self.due() {
+            return None;
+        }
+        let mut free = self.free_space()?;
+        if free < self.config.min_free && self.config.action == DiskAction::PurgeArchives {
+            if let Some(ro) = rotate {
+                while free < self.config.min_free && ro.purge_oldest() {
+                    free = self.free_space()?;
+                }
+            }
+        }
+        let engage = free < self.config.min_free;
+        if self.engaged.swap(engage, Ordering::AcqRel) == engage {
+            return None;
+        }
+        if engage {
+            Some(format!(
+                "disk free space {} bytes of {:#?} is below {}, {:?}",
+                free, self.dir, self.config.min_free, self.config.action
+            ))
+        } else {
+            Some(format!("disk free space {} bytes of {:#?} is recovered", free, self.dir))
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn route(&self, level: Level) -> GuardRoute {
+        if !self.engaged.load(Ordering::Relaxed) {
+            return GuardRoute::Write;
+        }
+        match self.config.action {
+            DiskAction::DropBelow(below) if level > below => GuardRoute::Drop,
+            DiskAction::Fallback => GuardRoute::Fallback,
+            _ => GuardRoute::Write,
+        }
+    }
+
+    fn free_space(&self) -> Option<u64> {
+        let path = CString::new(self.dir.as_os_str().as_bytes()).ok()?;
+        let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+            let e = std::io::Error::last_os_error();
+            report_error(SinkErrorKind::Stat, &self.dir.to_string_lossy(), &e);
+            return None;
+        }
+        Some(st.f_bavail as u64 * st.f_frsize as u64)
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    config::{Builder, SinkConfigBuild, SinkConfigTrait},
+    file_impl::Ticker,
+    formatter::RecordContext,
+    log_impl::{LogSink, LogSinkTrait},
+    metrics::SinkStats,
+    time::{Clock, Timer},
+};
+use log::{Level, Record};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+/// Interval to probe the primary sink
+const PROBE_INTERVAL_DEFAULT: Duration = Duration::from_secs(1);
+
+/// Config for a wrapper sink, routes the records to the secondary sink while the primary is
+/// unhealthy, and probes the primary periodically for recovery.
+///
+/// The primary is unhealthy when:
+///
+/// - [LogRawFile](crate::LogRawFile) or [LogBufFile](crate::LogBufFile) failed to open or write
+///   (EIO, ENOSPC), or the file is removed (checked on probe).
+///
+/// - [Syslog](crate::syslog::Syslog) gave up sending after the timeout.
+///
+/// A warning record is written to the secondary on failover, and to the primary on recovery.
+///
+/// NOTE: Since [LogBufFile](crate::LogBufFile) writes in the background, the lines it failed to
+/// write are handed over to the secondary as they are formatted by the primary. The lines partially
+/// written before the failure might be duplicated.
+///
+/// # Example
+///
+/// ``` rust
+/// use captains_log::*;
+/// let primary = LogRawFile::new("/tmp", "test.log", Level::Info, recipe::LOG_FORMAT_DEBUG);
+/// let secondary = LogConsole::new(ConsoleTarget::Stderr, Level::Info, recipe::LOG_FORMAT_DEBUG);
+/// let _ = Builder::default().add_sink(LogFailover::new(primary, secondary)).build();
+/// ```
+pub struct LogFailover {
+    pub primary: Box<dyn SinkConfigTrait>,
+
+    pub secondary: Box<dyn SinkConfigTrait>,
+
+    /// Interval to probe the primary, **default to be 1 sec**.
+    pub probe_interval: Duration,
+}
+
+impl LogFailover {
+    pub fn new<P: SinkConfigTrait, S: SinkConfigTrait>(primary: P, secondary: S) -> Self {
+        Self {
+            primary: Box::new(primary),
+            secondary: Box::new(secondary),
+            probe_interval: PROBE_INTERVAL_DEFAULT,
+        }
+    }
+
+    /// Set the interval to probe the primary
+    pub fn probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+}
+
+impl SinkConfigBuild for LogFailover {
+    fn build(&self, builder: &Builder) -> std::io::Result<LogSink> {
+        let secondary = Arc::new(self.secondary.build(builder)?);
+        let primary = self.primary.build(builder)?;
+        let spill = primary.set_spill(secondary.clone());
+        Ok(LogSink::Failover(LogSinkFailover {
+            primary: Box::new(primary),
+            secondary,
+            spill,
+            probe_interval: self.probe_interval,
+            last_probe: Ticker::new(),
+            failed: AtomicBool::new(false),
+            clock: builder.clock.clone(),
+        }))
+    }
+}
+
+impl SinkConfigTrait for LogFailover {
+    fn get_level(&self) -> Level {
+        self.primary.get_level().max(self.secondary.get_level())
+    }
+
+    fn get_file_path(&self) -> Option<Box<Path>> {
+        self.primary.get_file_path()
+    }
+
+    fn write_hash(&self, hasher: &mut Box<dyn Hasher>) {
+        self.primary.write_hash(hasher);
+        self.secondary.write_hash(hasher);
+        self.probe_interval.hash(hasher);
+        hasher.write(b"LogFailover");
+    }
+}
+
+pub(crate) struct LogSinkFailover {
+    primary: Box<LogSink>,
+    /// Shared with the primary to take over the lines failed to write
+    secondary: Arc<LogSink>,
+    /// The primary hands over the lines failed to write by itself
+    spill: bool,
+    probe_interval: Duration,
+    last_probe: Ticker,
+    /// The records are routed to secondary
+    failed: AtomicBool,
+    clock: Clock,
+}
+
+impl LogSinkFailover {
+    fn notice(&self, sink: &LogSink, msg: &str) {
+        let now = Timer::new(&self.clock);
+        sink.log(
+            &now,
+            &RecordContext::new(&[]),
+            &Record::builder()
+                .level(Level::Warn)
+                .target("captains_log")
+                .file_static(Some(file!()))
+                .line(Some(line!()))
+                .args(format_args!("{}", msg))
+                .build(),
+        );
+    }
+
+    #[inline]
+    fn set_failed(&self) {
+        if !self.failed.swap(true, Ordering::AcqRel) {
+            self.notice(&self.secondary, "primary log sink is unhealthy, failover to secondary");
+        }
+    }
+
+    fn probe(&self) {
+        if self.primary.probe() {
+            if self.failed.swap(false, Ordering::AcqRel) {
+                self.notice(&self.primary, "primary log sink is recovered from failover");
+            }
+        } else {
+            self.set_failed();
+        }
+    }
+}
+
+impl LogSinkTrait for LogSinkFailover {
+    fn open(&self) -> std::io::Result<()> {
+        self.secondary.open()?;
+        // The error is reported by the primary
+        if self.primary.open().is_err() {
+            self.set_failed();
+        }
+        Ok(())
+    }
+
+    fn reopen(&self) -> std::io::Result<()> {
+        let _ = self.primary.reopen();
+        self.secondary.reopen()
+    }
+
+    #[inline(always)]
+    fn log(&self, now: &Timer, ctx: &RecordContext, r: &Record) {
+        if self.last_probe.due(self.probe_interval) {
+            self.probe();
+        }
+        if !self.failed.load(Ordering::Acquire) {
+            if self.primary.healthy() {
+                self.primary.log(now, ctx, r);
+                if self.spill || self.primary.healthy() {
+                    return;
+                }
+            }
+            self.set_failed();
+        }
+        self.secondary.log(now, ctx, r);
+    }
+
+    #[inline(always)]
+    fn flush(&self) {
+        self.primary.flush();
+        self.secondary.flush();
+    }
+
+    fn scope_end(&self, scope: &Arc<str>, failed: bool) {
+        self.primary.scope_end(scope, failed);
+        self.secondary.scope_end(scope, failed);
+    }
+
+    fn stats(&self, out: &mut Vec<SinkStats>) {
+        self.primary.stats(out);
+        self.secondary.stats(out);
+    }
+
+    #[inline(always)]
+    fn healthy(&self) -> bool {
+        !self.failed.load(Ordering::Acquire) || self.secondary.healthy()
+    }
+}
@@ -1,19 +1,41 @@
 use crate::{
-    config::{LogFormat, SinkConfigBuild, SinkConfigTrait},
+    config::{Builder, LogFormat, SinkConfigBuild, SinkConfigTrait},
+    disk_guard::{DiskGuard, DiskGuardState, GuardRoute},
+    formatter::RecordContext,
     log_impl::{LogSink, LogSinkTrait},
-    time::Timer,
+    metrics::{SinkMetrics, SinkStats},
+    report::{report_error, SinkErrorKind},
+    rotation::*,
+    time::{Clock, ManualClock, Timer},
 };
 use log::{Level, Record};
+use parking_lot::Mutex;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use std::{fs::OpenOptions, os::unix::prelude::*, sync::Arc};
 
 use arc_swap::ArcSwapOption;
 
+/// Interval to check the file for rotation, when size limit is not reached.
+const ROTATE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Config for file sink that supports atomic append from multiprocess.
 ///
 /// Used when you want a reliable log regardless of crash or killed.
-/// For log rotation, you need system log-rotate service to notify with signal.
+/// For log rotation, you can either use system log-rotate service to notify with signal,
+/// or configure [LogRawFile::rotation()].
+///
+/// # Rotation among processes
+///
+/// Multiple processes can append to the same file with the same rotation config.
+/// They coordinate with `flock` on `<file>.lock`, so that exactly one process renames the file,
+/// while the others detect the inode change and reopen the new file.
+///
+/// Each process checks the file at most every 100ms, or when the size limit is reached by its
+/// own writes, so the rotated file may exceed the size limit slightly, and may contain a few
+/// lines after the boundary of [Rotation::align()].
 ///
 /// # Example
 ///
@@ -28,9 +50,10 @@ use arc_swap::ArcSwapOption;
 /// ) -> Builder {
 ///     let format = LogFormat::new(time_fmt, format_func);
 ///     let _file_path = file_path.into();
-///     let p = path::absolute(&_file_path).expect("path convert to absolute");
-///     let dir = p.parent().unwrap();
-///     let file_name = Path::new(p.file_name().unwrap());
+///     let p = path::absolute(&_file_path).unwrap_or(_file_path);
+///     // An invalid path is reported by build()
+///     let dir = p.parent().unwrap_or(Path::new(""));
+///     let file_name = Path::new(p.file_name().unwrap_or_default());
 ///     let file = LogRawFile::new(dir, file_name, max_level, format);
 ///     return Builder::default().signal(signal_hook::consts::SIGUSR1).add_sink(file);
 /// }
@@ -44,6 +67,23 @@ pub struct LogRawFile {
 
     /// path: dir/name
     pub file_path: Box<Path>,
+
+    /// Rotation config
+    pub rotation: Option<Rotation>,
+
+    /// Permissions, ownership and flags of the file and dir
+    pub file_options: FileOptions,
+
+    /// When to fdatasync the file, default to [SyncPolicy::None]
+    pub sync_policy: SyncPolicy,
+
+    /// Check the free space of the disk, default to None
+    pub disk_guard: Option<DiskGuard>,
+
+    /// Interval to check whether the file is deleted or moved, default to None
+    pub auto_reopen: Option<Duration>,
+
+    created_dirs: CreatedDirs,
 }
 
 impl LogRawFile {
@@ -58,17 +98,61 @@ impl LogRawFile {
         P2: Into<PathBuf>,
     {
         let dir_path: PathBuf = dir.into();
-        if !dir_path.exists() {
-            std::fs::create_dir(&dir_path).expect("create dir for log");
-        }
+        let created_dirs = CreatedDirs::new(&dir_path);
         let file_path = dir_path.join(file_name.into()).into_boxed_path();
-        Self { level, format, file_path }
+        Self {
+            level,
+            format,
+            file_path,
+            rotation: None,
+            file_options: FileOptions::default(),
+            sync_policy: SyncPolicy::None,
+            disk_guard: None,
+            auto_reopen: None,
+            created_dirs,
+        }
+    }
+
+    /// Rotate the file by the sink itself, see [rotation](crate::rotation).
+    pub fn rotation(mut self, ro: Rotation) -> Self {
+        self.rotation = Some(ro);
+        self
+    }
+
+    /// Set the permissions, ownership and flags of the file and dir.
+    pub fn file_options(mut self, opts: FileOptions) -> Self {
+        self.file_options = opts;
+        self
+    }
+
+    /// Set the durability of the file, see [SyncPolicy].
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Act when the free space of the disk is low, see [DiskGuard].
+    pub fn disk_guard(mut self, guard: DiskGuard) -> Self {
+        self.disk_guard = Some(guard);
+        self
+    }
+
+    /// Check periodically whether the file is deleted or moved (the inode and dev of the path
+    /// differ from the opened file), and reopen it. An alternative to
+    /// [Builder::signal()](crate::Builder::signal()) when the file is removed or moved
+    /// without notifying.
+    ///
+    /// The check is performed on writing, and costs a `stat` per interval.
+    pub fn auto_reopen(mut self, interval: Duration) -> Self {
+        self.auto_reopen = Some(interval);
+        self
     }
 }
 
 impl SinkConfigBuild for LogRawFile {
-    fn build(&self) -> LogSink {
-        LogSink::File(LogSinkFile::new(self))
+    fn build(&self, builder: &Builder) -> std::io::Result<LogSink> {
+        self.created_dirs.apply(&self.file_options)?;
+        Ok(LogSink::File(LogSinkFile::new(self, &builder.clock, builder.latency_enabled())?))
     }
 }
 
@@ -93,72 +177,793 @@ pub(crate) struct LogSinkFile {
     // raw fd only valid before original File close, use ArcSwap to prevent drop while using.
     f: ArcSwapOption<std::fs::File>,
     formatter: LogFormat,
+    rotate: Option<RawFileRotate>,
+    file_options: FileOptions,
+    sync: FileSync,
+    guard: Option<DiskGuardState>,
+    clock: Clock,
+    healthy: AtomicBool,
+    metrics: SinkMetrics,
+    watch: Option<PathWatch>,
 }
 
 pub(crate) fn open_file(path: &Path) -> std::io::Result<std::fs::File> {
     OpenOptions::new().append(true).create(true).open(path)
 }
 
-impl LogSinkFile {
-    fn new(config: &LogRawFile) -> Self {
+/// Permissions, ownership and flags for the log files, archives and dirs, configured by
+/// [LogRawFile::file_options()], [LogBufFile::file_options()](crate::LogBufFile::file_options())
+/// and [Rotation::file_options()].
+///
+/// The files are always opened with `O_CLOEXEC`.
+///
+/// # Example
+///
+/// ``` rust
+/// use captains_log::*;
+/// let file = LogRawFile::new("/tmp", "secure.log", Level::Info, recipe::LOG_FORMAT_DEBUG)
+///     .file_options(FileOptions::default().mode(0o640).dir_mode(0o750).nofollow());
+/// ```
+#[derive(Hash, Clone, Copy, Default, PartialEq, Debug)]
+pub struct FileOptions {
+    /// Permission bits of the files, when None, 0o666 masked by umask.
+    pub mode: Option<u32>,
+    /// Permission bits of the dirs created by us (including the parents), when None, 0o777 masked
+    /// by umask.
+    pub dir_mode: Option<u32>,
+    /// Change the owner of the files and the dirs created by us.
+    pub uid: Option<u32>,
+    /// Change the group of the files and the dirs created by us.
+    pub gid: Option<u32>,
+    /// Open with `O_NOFOLLOW`, refuse to write when the path is a symlink.
+    /// Not compatible with [Rotation::symlink()].
+    pub nofollow: bool,
+}
+
+impl FileOptions {
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.dir_mode = Some(mode);
+        self
+    }
+
+    /// chown to uid and gid, None to leave unchanged.
+    pub fn owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    pub fn nofollow(mut self) -> Self {
+        self.nofollow = true;
+        self
+    }
+
+    /// Open the file for append, create if not exists.
+    pub(crate) fn open(&self, path: &Path) -> std::io::Result<std::fs::File> {
+        let mut oo = OpenOptions::new();
+        oo.append(true).create(true);
+        if let Some(mode) = self.mode {
+            oo.mode(mode);
+        }
+        if self.nofollow {
+            oo.custom_flags(libc::O_NOFOLLOW);
+        }
+        let f = oo.open(path)?;
+        self.apply(&f)?;
+        Ok(f)
+    }
+
+    /// Set the mode (which might be masked by umask on creation) and owner of the file.
+    pub(crate) fn apply(&self, f: &std::fs::File) -> std::io::Result<()> {
+        if self.mode.is_none() && self.uid.is_none() && self.gid.is_none() {
+            return Ok(());
+        }
+        let mt = f.metadata()?;
+        if let Some(mode) = self.mode {
+            if mt.mode() & 0o7777 != mode {
+                f.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            }
+        }
+        let uid = self.uid.filter(|uid| *uid != mt.uid());
+        let gid = self.gid.filter(|gid| *gid != mt.gid());
+        if uid.is_some() || gid.is_some() {
+            std::os::unix::fs::fchown(f, uid, gid)?;
+        }
+        Ok(())
+    }
+
+    /// Same as apply(), for the file which is not opened. Refuse to follow the symlink, which
+    /// might be placed to change the permission of another file.
+    pub(crate) fn apply_path(&self, path: &Path) -> std::io::Result<()> {
+        if self.mode.is_none() && self.uid.is_none() && self.gid.is_none() {
+            return Ok(());
+        }
+        self.apply(&OpenOptions::new().read(true).custom_flags(libc::O_NOFOLLOW).open(path)?)
+    }
+
+    /// Set the dir_mode (which might be masked by umask on creation) and owner of the dir.
+    fn apply_dir(&self, dir: &Path) -> std::io::Result<()> {
+        if self.dir_mode.is_none() && self.uid.is_none() && self.gid.is_none() {
+            return Ok(());
+        }
+        let f = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+            .open(dir)?;
+        if let Some(mode) = self.dir_mode {
+            f.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        }
+        if self.uid.is_some() || self.gid.is_some() {
+            std::os::unix::fs::fchown(&f, self.uid, self.gid)?;
+        }
+        Ok(())
+    }
+
+    /// Create the dir and its parents if not exists.
+    pub(crate) fn create_dir(&self, dir: &Path) -> std::io::Result<()> {
+        self.create_dirs(dir).map(|_| ())
+    }
+
+    /// Create the dir and its parents if not exists, with dir_mode and owner applied to each of
+    /// them. Returns the dirs created.
+    fn create_dirs(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut missing = Vec::new();
+        let mut p = Some(dir);
+        while let Some(d) = p {
+            if d.as_os_str().is_empty() || d.is_dir() {
+                break;
+            }
+            missing.push(d);
+            p = d.parent();
+        }
+        use std::os::unix::fs::DirBuilderExt;
+        let mut builder = std::fs::DirBuilder::new();
+        if let Some(mode) = self.dir_mode {
+            builder.mode(mode);
+        }
+        let mut created = Vec::with_capacity(missing.len());
+        for d in missing.into_iter().rev() {
+            if let Err(e) = builder.create(d) {
+                // Created by others concurrently
+                if e.kind() == std::io::ErrorKind::AlreadyExists && d.is_dir() {
+                    continue;
+                }
+                return Err(e);
+            }
+            self.apply_dir(d)?;
+            created.push(d.to_path_buf());
+        }
+        Ok(created)
+    }
+}
+
+/// The dirs created by the constructor of [LogRawFile] and [LogBufFile](crate::LogBufFile),
+/// [FileOptions] is applied to them on build.
+#[derive(Default)]
+pub(crate) struct CreatedDirs(Vec<PathBuf>);
+
+impl CreatedDirs {
+    /// Create the dir with the default options, the error is returned by the retry on build.
+    pub(crate) fn new(dir: &Path) -> Self {
+        Self(FileOptions::default().create_dirs(dir).unwrap_or_default())
+    }
+
+    pub(crate) fn apply(&self, opts: &FileOptions) -> std::io::Result<()> {
+        for dir in self.0.iter() {
+            if dir.is_dir() {
+                opts.apply_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Not a part of the config
+impl Hash for CreatedDirs {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+/// Write the whole buffer with write(2)
+pub(crate) fn write_fd(fd: libc::c_int, buf: &[u8]) -> std::io::Result<()> {
+    let mut p = buf.as_ptr();
+    let mut l = buf.len();
+    loop {
+        let r = unsafe { libc::write(fd, p as *const libc::c_void, l) };
+        if r < 0 {
+            // Do not panic on write error (disk err, space err), should not affect the program
+            return Err(std::io::Error::last_os_error());
+        }
+        if r == l as isize {
+            return Ok(());
+        }
+        // NOTE: If early return happens, means you are using a filesystem not
+        // supporting atomic append
+        l -= r as usize;
+        p = unsafe { p.add(r as usize) };
+    }
+}
+
+/// When to fdatasync the log file, configured by [LogRawFile::sync_policy()] and
+/// [LogBufFile::sync_policy()](crate::LogBufFile::sync_policy()).
+///
+/// Without fdatasync, the written records survive a crash of the process, but not a crash of
+/// the OS or power failure. The cost grows with the frequency of sync, PerRecord is the most
+/// expensive. Run the benchmark with `make bench bench_file_sync_policy` on your disk.
+///
+/// # Example
+///
+/// ``` rust
+/// use captains_log::*;
+/// use std::time::Duration;
+/// let file = LogRawFile::new("/tmp", "audit.log", Level::Info, recipe::LOG_FORMAT_DEBUG)
+///     .sync_policy(SyncPolicy::Periodic(Duration::from_millis(500)));
+/// ```
+#[derive(Hash, Clone, Copy, Default, PartialEq, Debug)]
+pub enum SyncPolicy {
+    /// Leave it to the OS
+    #[default]
+    None,
+    /// fdatasync when the interval has elapsed since the last sync, measured by the
+    /// [ManualClock](crate::ManualClock) if [Builder::clock()](crate::Builder::clock()) is set to
+    /// one.
+    ///
+    /// It's checked on writes of the raw file, and also when idle for the buffered file
+    /// (every `flush_millis`).
+    Periodic(Duration),
+    /// fdatasync when the buffered file writes a batch of records,
+    /// or on [GlobalLogger::flush()](crate::GlobalLogger) for the raw file.
+    PerFlush,
+    /// fdatasync after each record, the buffered file writes each record without batching.
+    PerRecord,
+}
+
+/// Perform the fdatasync according to [SyncPolicy].
+pub(crate) struct FileSync {
+    pub(crate) policy: SyncPolicy,
+    path: Box<Path>,
+    last_sync: Ticker,
+    /// written since last sync
+    dirty: AtomicBool,
+}
+
+impl FileSync {
+    pub(crate) fn new(policy: SyncPolicy, path: &Path, clock: &Clock) -> Self {
         Self {
+            policy,
+            path: path.into(),
+            last_sync: Ticker::with_clock(clock),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// After writing a record (raw file), or a batch of records (`batch` for buffered file).
+    #[inline(always)]
+    pub(crate) fn on_write(&self, f: &std::fs::File, batch: bool) {
+        let sync = match self.policy {
+            SyncPolicy::None => return,
+            SyncPolicy::Periodic(interval) => self.last_sync.due(interval),
+            SyncPolicy::PerFlush => batch,
+            SyncPolicy::PerRecord => true,
+        };
+        if sync {
+            self.sync(f);
+        } else {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// On explicit flush, sync whatever written.
+    #[inline]
+    pub(crate) fn on_flush(&self, f: &std::fs::File) {
+        if self.policy != SyncPolicy::None && self.dirty.load(Ordering::Relaxed) {
+            self.sync(f);
+        }
+    }
+
+    /// When idle, only for [SyncPolicy::Periodic]
+    #[inline]
+    pub(crate) fn on_idle(&self, f: &std::fs::File) {
+        if let SyncPolicy::Periodic(interval) = self.policy {
+            if self.dirty.load(Ordering::Relaxed) && self.last_sync.due(interval) {
+                self.sync(f);
+            }
+        }
+    }
+
+    pub(crate) fn sync(&self, f: &std::fs::File) {
+        self.dirty.store(false, Ordering::Relaxed);
+        if let SyncPolicy::Periodic(_) = self.policy {
+            self.last_sync.reset();
+        }
+        if let Err(e) = f.sync_data() {
+            report_error(SinkErrorKind::Sync, &self.path.to_string_lossy(), &e);
+        }
+    }
+}
+
+/// For periodic tasks shared by multiple threads.
+pub(crate) struct Ticker {
+    start: Instant,
+    /// Follow the manual clock (and the time it started) instead of Instant
+    manual: Option<(Arc<ManualClock>, SystemTime)>,
+    /// milliseconds since start
+    last: AtomicU64,
+}
+
+impl Ticker {
+    pub(crate) fn new() -> Self {
+        Self { start: Instant::now(), manual: None, last: AtomicU64::new(0) }
+    }
+
+    pub(crate) fn with_clock(clock: &Clock) -> Self {
+        let mut ticker = Self::new();
+        if let Clock::Manual(c) = clock {
+            ticker.manual = Some((c.clone(), c.now()));
+        }
+        ticker
+    }
+
+    #[inline(always)]
+    fn elapsed(&self) -> u64 {
+        let elapsed = match self.manual.as_ref() {
+            Some((c, start)) => c.now().duration_since(*start).unwrap_or_default(),
+            None => self.start.elapsed(),
+        };
+        elapsed.as_millis() as u64
+    }
+
+    /// Return true when the interval has elapsed since last time, only one thread get true.
+    #[inline]
+    pub(crate) fn due(&self, interval: Duration) -> bool {
+        let now = self.elapsed();
+        let last = self.last.load(Ordering::Relaxed);
+        if now.saturating_sub(last) < interval.as_millis() as u64 {
+            return false;
+        }
+        self.last.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+    }
+
+    #[inline]
+    pub(crate) fn reset(&self) {
+        self.last.store(self.elapsed(), Ordering::Relaxed);
+    }
+}
+
+/// Reopen the file when the path points to another file, see [LogRawFile::auto_reopen()].
+pub(crate) struct PathWatch {
+    interval: Duration,
+    last_check: Ticker,
+}
+
+impl PathWatch {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self { interval, last_check: Ticker::new() }
+    }
+
+    /// Return true when the interval has elapsed, and the file is deleted or moved.
+    #[inline(always)]
+    pub(crate) fn check(&self, path: &Path, f: &std::fs::File) -> bool {
+        if !self.last_check.due(self.interval) {
+            return false;
+        }
+        match (std::fs::metadata(path), f.metadata()) {
+            (Ok(p), Ok(f)) => p.ino() != f.ino() || p.dev() != f.dev(),
+            // The path is removed
+            (Err(_), _) => true,
+            (_, Err(_)) => false,
+        }
+    }
+}
+
+/// The xattr to persist the creation time, when the filesystem does not support birth time.
+const XATTR_CREATE_TIME: &[u8] = b"user.captains_log.create_time\0";
+
+/// The creation time of the file, for rotation by age after restart.
+///
+/// Use the birth time (statx) when the filesystem supports it, otherwise the time recorded in
+/// xattr by [record_create_time()]. When neither is available, fallback to mtime and record it.
+/// Never later than the mtime and `now` of our clock.
+pub(crate) fn file_create_time(
+    f: &std::fs::File, mt: &std::fs::Metadata, now: SystemTime,
+) -> SystemTime {
+    let mtime = mt.modified().unwrap_or(now).min(now);
+    if let Ok(btime) = mt.created() {
+        return btime.min(mtime);
+    }
+    if let Some(t) = get_xattr_time(f) {
+        return t.min(mtime);
+    }
+    record_create_time(f, mt, mtime);
+    mtime
+}
+
+/// Record the creation time in xattr, if the filesystem does not support birth time.
+pub(crate) fn record_create_time(f: &std::fs::File, mt: &std::fs::Metadata, t: SystemTime) {
+    if mt.created().is_ok() {
+        return;
+    }
+    // Fallback to mtime on next start if the filesystem does not support user xattr either
+    let _ = set_xattr_time(f, t);
+}
+
+fn set_xattr_time(_f: &std::fs::File, _t: SystemTime) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let d = _t
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        let v = d.as_nanos().to_string();
+        let r = unsafe {
+            libc::fsetxattr(
+                _f.as_raw_fd(),
+                XATTR_CREATE_TIME.as_ptr() as *const libc::c_char,
+                v.as_ptr() as *const libc::c_void,
+                v.len(),
+                0,
+            )
+        };
+        if r < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[inline]
+fn get_xattr_time(_f: &std::fs::File) -> Option<SystemTime> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut buf = [0u8; 40];
+        let r = unsafe {
+            libc::fgetxattr(
+                _f.as_raw_fd(),
+                XATTR_CREATE_TIME.as_ptr() as *const libc::c_char,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if r > 0 {
+            let nanos: u64 = std::str::from_utf8(&buf[..r as usize]).ok()?.parse().ok()?;
+            return Some(std::time::UNIX_EPOCH + Duration::from_nanos(nanos));
+        }
+    }
+    None
+}
+
+struct RawFileRotate {
+    rotate: LogRotate,
+    lock_path: PathBuf,
+    clock: Clock,
+    state: Mutex<RawFileState>,
+}
+
+/// The file currently opened by this process
+struct RawFileState {
+    create_time: SystemTime,
+    /// Size from last fstat, plus the bytes written by this process since then.
+    size: u64,
+    dev: u64,
+    ino: u64,
+    last_check: Instant,
+}
+
+impl FileSinkTrait for RawFileState {
+    #[inline(always)]
+    fn get_create_time(&self) -> SystemTime {
+        self.create_time
+    }
+
+    #[inline(always)]
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl LogSinkFile {
+    fn new(config: &LogRawFile, clock: &Clock, timed: bool) -> std::io::Result<Self> {
+        let mut rotate = None;
+        if let Some(ro) = config.rotation.as_ref() {
+            let mut lock_path = config.file_path.as_os_str().to_os_string();
+            lock_path.push(".lock");
+            let lock_path = PathBuf::from(lock_path);
+            rotate = Some(RawFileRotate {
+                rotate: ro.build(
+                    &config.file_path,
+                    clock,
+                    &config.file_options,
+                    Some(&lock_path),
+                )?,
+                lock_path,
+                clock: clock.clone(),
+                state: Mutex::new(RawFileState {
+                    create_time: clock.now(),
+                    size: 0,
+                    dev: 0,
+                    ino: 0,
+                    last_check: Instant::now(),
+                }),
+            });
+        }
+        Ok(Self {
             path: config.file_path.clone(),
             max_level: config.level,
             formatter: config.format.clone(),
             f: ArcSwapOption::new(None),
-        }
+            rotate,
+            file_options: config.file_options,
+            sync: FileSync::new(config.sync_policy, &config.file_path, clock),
+            guard: config
+                .disk_guard
+                .map(|g| DiskGuardState::new(g, &config.file_path, Default::default())),
+            clock: clock.clone(),
+            healthy: AtomicBool::new(true),
+            metrics: SinkMetrics::new(timed),
+            watch: config.auto_reopen.map(PathWatch::new),
+        })
     }
-}
 
-impl LogSinkTrait for LogSinkFile {
-    #[inline]
-    fn open(&self) -> std::io::Result<()> {
-        self.reopen()
+    /// Check the free space, and write the notice when the guard engages or releases.
+    fn check_guard(&self, guard: &DiskGuardState, file: &std::fs::File, force: bool) {
+        let notice = if let Some(ro) = self.rotate.as_ref() {
+            if !force && !guard.due() {
+                return;
+            }
+            let _state = ro.state.lock();
+            guard.check(Some(&ro.rotate), true)
+        } else {
+            guard.check(None, force)
+        };
+        if let Some(msg) = notice {
+            let buf =
+                self.formatter.process_notice(&self.clock, Level::Warn, format_args!("{}", msg));
+            if guard.route(Level::Warn) == GuardRoute::Fallback {
+                guard.write_fallback(&buf);
+            } else {
+                let _ = write_fd(file.as_raw_fd() as libc::c_int, buf.as_bytes());
+            }
+        }
     }
 
-    fn reopen(&self) -> std::io::Result<()> {
-        match open_file(&self.path) {
+    fn reopen_file(&self, rotated: bool, state: Option<&mut RawFileState>) -> std::io::Result<()> {
+        match self.file_options.open(&self.path) {
             Ok(f) => {
+                if let (Some(ro), Some(state)) = (self.rotate.as_ref(), state) {
+                    let mt = f.metadata()?;
+                    if rotated || state.ino != mt.ino() || state.dev != mt.dev() {
+                        let now = ro.clock.now();
+                        state.create_time = if rotated {
+                            record_create_time(&f, &mt, now);
+                            now
+                        } else {
+                            file_create_time(&f, &mt, now)
+                        };
+                    }
+                    state.size = mt.len();
+                    state.dev = mt.dev();
+                    state.ino = mt.ino();
+                    state.last_check = Instant::now();
+                }
                 self.f.store(Some(Arc::new(f)));
+                self.healthy.store(true, Ordering::Release);
                 Ok(())
             }
             Err(e) => {
-                eprintln!("open logfile {:#?} failed: {:?}", &self.path, e);
+                report_error(SinkErrorKind::Open, &self.path.to_string_lossy(), &e);
+                self.healthy.store(false, Ordering::Release);
                 Err(e)
             }
         }
     }
 
+    /// Whether the path is pointing to another file, (rotated by other process or removed)
+    #[inline]
+    fn is_path_changed(&self, state: &RawFileState) -> bool {
+        match std::fs::metadata(&self.path) {
+            Ok(mt) => mt.ino() != state.ino || mt.dev() != state.dev,
+            Err(_) => true,
+        }
+    }
+
+    /// Before writing, reopen if the file is rotated by other process since the last check
+    /// (when idle longer than ROTATE_CHECK_INTERVAL), so the line is not written to the archive.
+    #[inline]
+    fn check_rotated(&self, ro: &RawFileRotate) -> Option<Arc<std::fs::File>> {
+        let mut state = ro.state.try_lock()?;
+        if state.last_check.elapsed() < ROTATE_CHECK_INTERVAL || !self.is_path_changed(&state) {
+            return None;
+        }
+        self.reopen_file(true, Some(&mut state)).ok()?;
+        self.f.load_full()
+    }
+
+    fn check_rotate(&self, ro: &RawFileRotate, written: usize, force: bool) {
+        let mut state = if force {
+            ro.state.lock()
+        } else {
+            // Other thread is checking
+            match ro.state.try_lock() {
+                Some(state) => state,
+                None => return,
+            }
+        };
+        state.size += written as u64;
+        if !force && state.last_check.elapsed() < ROTATE_CHECK_INTERVAL {
+            match ro.rotate.size_limit() {
+                Some(limit) if state.size > limit => {}
+                _ => return,
+            }
+        }
+        state.last_check = Instant::now();
+        if self.is_path_changed(&state) {
+            let _ = self.reopen_file(true, Some(&mut state));
+            return;
+        }
+        if let Some(file) = self.f.load_full() {
+            if let Ok(mt) = file.metadata() {
+                state.size = mt.len();
+            }
+        }
+        if !ro.rotate.need_rotate(&*state) {
+            return;
+        }
+        // The cleanup thread of last rotation holds the lock
+        ro.rotate.wait();
+        let _lock = match FileLock::lock(&ro.lock_path, ro.rotate.file_options()) {
+            Ok(lock) => lock,
+            Err(e) => {
+                ro.rotate.on_error(SinkErrorKind::Lock, &ro.lock_path, &e);
+                return;
+            }
+        };
+        // Check again with the lock held, other process might have done the rotation.
+        // Other processes notice the rename within ROTATE_CHECK_INTERVAL, doubled for the writes
+        // in flight.
+        if !self.is_path_changed(&state)
+            && ro.rotate.rotate_shared(&*state, &ro.lock_path, ROTATE_CHECK_INTERVAL * 2)
+        {
+            self.metrics.on_rotate();
+        }
+        let _ = self.reopen_file(true, Some(&mut state));
+    }
+}
+
+impl LogSinkTrait for LogSinkFile {
+    #[inline]
+    fn open(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            self.file_options.create_dir(dir)?;
+        }
+        self.reopen()?;
+        if let Some(ro) = self.rotate.as_ref() {
+            // For rotate_on_start, and the file already expired.
+            self.check_rotate(ro, 0, true);
+        }
+        if let (Some(guard), Some(file)) = (self.guard.as_ref(), self.f.load_full()) {
+            self.check_guard(guard, &file, true);
+        }
+        Ok(())
+    }
+
+    fn reopen(&self) -> std::io::Result<()> {
+        if let Some(ro) = self.rotate.as_ref() {
+            self.reopen_file(false, Some(&mut ro.state.lock()))
+        } else {
+            self.reopen_file(false, None)
+        }
+    }
+
     #[inline(always)]
-    fn log(&self, now: &Timer, r: &Record) {
+    fn log(&self, now: &Timer, ctx: &RecordContext, r: &Record) {
         if r.level() <= self.max_level {
+            self.metrics.on_accept();
             // ArcSwap ensure file fd is not close during reopen for log rotation,
             // in case of panic during write.
-            if let Some(file) = self.f.load_full() {
+            if let Some(mut file) = self.f.load_full() {
+                if let Some(watch) = self.watch.as_ref() {
+                    if watch.check(&self.path, &file) && self.reopen().is_ok() {
+                        if let Some(f) = self.f.load_full() {
+                            file = f;
+                        }
+                    }
+                }
+                if let Some(f) = self.rotate.as_ref().and_then(|ro| self.check_rotated(ro)) {
+                    file = f;
+                }
+                let mut fallback = false;
+                if let Some(guard) = self.guard.as_ref() {
+                    self.check_guard(guard, &file, false);
+                    match guard.route(r.level()) {
+                        GuardRoute::Write => {}
+                        GuardRoute::Drop => return,
+                        GuardRoute::Fallback => fallback = true,
+                    }
+                }
                 // Get a stable buffer,
                 // for concurrently write to file from multi process.
-                let buf = self.formatter.process(now, r);
-                let mut p = buf.as_ptr();
-                let mut l = buf.len();
-                loop {
-                    let r = unsafe {
-                        libc::write(file.as_raw_fd() as libc::c_int, p as *const libc::c_void, l)
-                    };
-                    if r == l as isize || r < 0 {
-                        // Ignore write error (disk err, space err), should not affect the program
-                        return;
+                let buf = self.formatter.process(now, ctx, r);
+                if fallback {
+                    if let Some(guard) = self.guard.as_ref() {
+                        guard.write_fallback(&buf);
                     }
-                    // NOTE: If early return happens, means you are using a filesystem not
-                    // supporting atomic append
-                    l -= r as usize;
-                    p = unsafe { p.add(r as usize) };
+                    return;
                 }
+                let start = self.metrics.start();
+                if let Err(e) = write_fd(file.as_raw_fd() as libc::c_int, buf.as_bytes()) {
+                    self.metrics.on_error();
+                    if self.healthy.swap(false, Ordering::AcqRel) {
+                        report_error(SinkErrorKind::Write, &self.path.to_string_lossy(), &e);
+                    }
+                    return;
+                }
+                self.metrics.on_write(buf.len(), start);
+                self.sync.on_write(&file, false);
+                if let Some(ro) = self.rotate.as_ref() {
+                    self.check_rotate(ro, buf.len(), false);
+                }
+            }
+        }
+    }
+
+    fn write_formatted(&self, buf: &[u8]) {
+        if let Some(file) = self.f.load_full() {
+            let start = self.metrics.start();
+            if write_fd(file.as_raw_fd() as libc::c_int, buf).is_err() {
+                self.metrics.on_error();
+                return;
             }
+            self.metrics.on_write(buf.len(), start);
+            if let Some(ro) = self.rotate.as_ref() {
+                self.check_rotate(ro, buf.len(), false);
+            }
+        }
+    }
+
+    /// The lines failed to write are not handed over, only those routed by DiskAction::Fallback.
+    fn set_spill(&self, sink: Arc<LogSink>) -> bool {
+        if let Some(guard) = self.guard.as_ref() {
+            guard.set_fallback(sink);
+        }
+        false
+    }
+
+    #[inline(always)]
+    fn healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    fn stats(&self, out: &mut Vec<SinkStats>) {
+        out.push(self.metrics.snapshot(self.path.display().to_string(), 0));
+    }
+
+    /// Reopen when the file is removed or failed to write.
+    fn probe(&self) -> bool {
+        let removed = match self.f.load_full() {
+            Some(file) => file.metadata().map(|mt| mt.nlink() == 0).unwrap_or(true),
+            None => true,
+        };
+        if removed || !self.healthy() {
+            let _ = self.reopen();
         }
+        self.healthy()
     }
 
     #[inline(always)]
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(file) = self.f.load_full() {
+            self.sync.on_flush(&file);
+        }
+        if let Some(ro) = self.rotate.as_ref() {
+            self.check_rotate(ro, 0, true);
+            ro.rotate.wait();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -178,4 +983,73 @@ mod tests {
         assert!(dir_path.is_dir());
         std::fs::remove_dir(&dir_path).expect("ok");
     }
+
+    #[test]
+    fn test_xattr_create_time() {
+        let path = Path::new("/tmp/test_xattr_create_time.log");
+        let _ = std::fs::remove_file(path);
+        let f = std::fs::File::create(path).expect("create");
+        assert_eq!(get_xattr_time(&f), None);
+        let t = std::time::UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789);
+        if set_xattr_time(&f, t).is_err() {
+            // For example tmpfs without user xattr
+            std::fs::remove_file(path).expect("ok");
+            return;
+        }
+        assert_eq!(get_xattr_time(&f), Some(t));
+        // Persisted after reopen
+        drop(f);
+        let f = std::fs::File::open(path).expect("open");
+        assert_eq!(get_xattr_time(&f), Some(t));
+        std::fs::remove_file(path).expect("ok");
+    }
+
+    #[test]
+    fn test_file_sync() {
+        let path = Path::new("/tmp/test_file_sync.log");
+        let f = std::fs::File::create(path).expect("create");
+        // dirty is cleared by sync
+        let dirty = |sync: &FileSync| sync.dirty.load(Ordering::Relaxed);
+
+        let sync = FileSync::new(SyncPolicy::None, path, &Clock::Real);
+        sync.on_write(&f, true);
+        sync.on_flush(&f);
+        assert!(!dirty(&sync));
+
+        let sync = FileSync::new(SyncPolicy::PerRecord, path, &Clock::Real);
+        sync.on_write(&f, false);
+        assert!(!dirty(&sync));
+
+        let sync = FileSync::new(SyncPolicy::PerFlush, path, &Clock::Real);
+        sync.on_write(&f, false);
+        assert!(dirty(&sync));
+        sync.on_flush(&f);
+        assert!(!dirty(&sync));
+        sync.on_write(&f, true);
+        assert!(!dirty(&sync));
+        sync.on_write(&f, false);
+        sync.on_idle(&f);
+        assert!(dirty(&sync));
+
+        let clock = Arc::new(ManualClock::new(SystemTime::now()));
+        let policy = SyncPolicy::Periodic(Duration::from_secs(1));
+        let sync = FileSync::new(policy, path, &Clock::Manual(clock.clone()));
+        sync.on_write(&f, true);
+        assert!(dirty(&sync));
+        clock.advance(Duration::from_millis(500));
+        sync.on_write(&f, false);
+        sync.on_idle(&f);
+        assert!(dirty(&sync));
+        clock.advance(Duration::from_millis(500));
+        sync.on_idle(&f);
+        assert!(!dirty(&sync));
+        // The interval restarts from the last sync
+        clock.advance(Duration::from_millis(500));
+        sync.on_write(&f, false);
+        assert!(dirty(&sync));
+        clock.advance(Duration::from_millis(500));
+        sync.on_write(&f, false);
+        assert!(!dirty(&sync));
+        std::fs::remove_file(path).expect("ok");
+    }
 }
@@ -1,17 +1,28 @@
 use log::{kv::Key, *};
-use std::{fmt, thread};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Once, OnceLock,
+    },
+    thread,
+};
 
-use crate::time::Timer;
+use crate::time::{TimeZone, Timer};
+
+/// Sequence number of the records, shared by all the sinks.
+static SEQ: AtomicU64 = AtomicU64::new(0);
 
 pub struct TimeFormatter<'a> {
     pub now: &'a Timer,
     pub fmt_str: &'a str,
+    pub tz: TimeZone,
 }
 
 impl<'a> TimeFormatter<'a> {
     #[inline(always)]
     fn time_str(&self) -> String {
-        self.now.format(self.fmt_str).to_string()
+        self.now.with_zone(self.tz).format(self.fmt_str).to_string()
     }
 }
 
@@ -19,6 +30,7 @@ impl<'a> TimeFormatter<'a> {
 pub struct FormatRecord<'a> {
     pub record: &'a Record<'a>,
     pub time: TimeFormatter<'a>,
+    pub(crate) ctx: &'a RecordContext<'a>,
 }
 
 impl<'a> FormatRecord<'a> {
@@ -32,11 +44,27 @@ impl<'a> FormatRecord<'a> {
         self.record.line().unwrap_or(0)
     }
 
+    #[inline(always)]
+    pub fn target(&self) -> &'a str {
+        self.record.target()
+    }
+
+    #[inline(always)]
+    pub fn module_path(&self) -> &'a str {
+        self.record.module_path().unwrap_or("<none>")
+    }
+
     #[inline(always)]
     pub fn timestamp_nano(&self) -> i64 {
         self.time.now.timestamp_nanos_opt().unwrap_or(0)
     }
 
+    /// Milliseconds since UNIX epoch, regardless of the timezone.
+    #[inline(always)]
+    pub fn timestamp_millis(&self) -> i64 {
+        self.time.now.timestamp_millis()
+    }
+
     #[inline(always)]
     pub fn time(&self) -> String {
         self.time.time_str()
@@ -64,8 +92,137 @@ impl<'a> FormatRecord<'a> {
 
     #[inline(always)]
     pub fn thread_id(&self) -> thread::ThreadId {
+        if let Some(ctx) = self.ctx.thread {
+            return ctx.thread.id();
+        }
         thread::current().id()
     }
+
+    /// The name of current thread, None if unnamed.
+    #[inline(always)]
+    pub fn thread_name(&self) -> Option<&str> {
+        if let Some(ctx) = self.ctx.thread {
+            return ctx.thread.name();
+        }
+        THREAD_NAME
+            .try_with(|name| {
+                // Safety: FormatRecord is not Send, so the borrow does not outlive current
+                // thread, and the cache is never modified after initialization.
+                name.as_deref().map(|name| unsafe { &*(name as *const str) })
+            })
+            .ok()
+            .flatten()
+    }
+
+    /// The process id, cached and reset in the child process after fork.
+    #[inline(always)]
+    pub fn pid(&self) -> u32 {
+        match PID.load(Ordering::Relaxed) {
+            0 => cache_pid(),
+            pid => pid,
+        }
+    }
+
+    /// The hostname, cached on first call.
+    #[inline(always)]
+    pub fn hostname(&self) -> &'static str {
+        static HOSTNAME: OnceLock<String> = OnceLock::new();
+        HOSTNAME.get_or_init(get_hostname)
+    }
+
+    /// A monotonic sequence number of the records since the process started.
+    /// The same record have the same number in all the sinks.
+    #[inline(always)]
+    pub fn seq(&self) -> u64 {
+        self.ctx.seq
+    }
+
+    /// The static fields configured by [Builder::with_field()](crate::Builder::with_field()),
+    /// in the order of configuration.
+    #[inline(always)]
+    pub fn fields(&self) -> &'a [(String, String)] {
+        self.ctx.fields
+    }
+
+    /// Get a static field configured by [Builder::with_field()](crate::Builder::with_field())
+    #[inline(always)]
+    pub fn field(&self, key: &str) -> Option<&'a str> {
+        self.fields().iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// The name of [LogScope](crate::scope::LogScope) entered by `#[logfn]`, usually the test name.
+    #[inline(always)]
+    pub fn scope(&self) -> Option<Arc<str>> {
+        if let Some(ctx) = self.ctx.thread {
+            return ctx.scope.clone();
+        }
+        crate::scope::current_scope()
+    }
+}
+
+/// The per-record context shared by all the sinks, passed along with the [Timer].
+pub(crate) struct RecordContext<'a> {
+    seq: u64,
+    /// The static fields configured by [Builder::with_field()](crate::Builder::with_field())
+    pub(crate) fields: &'a [(String, String)],
+    /// The logging thread, when the record is formatted in another thread
+    thread: Option<&'a ThreadContext>,
+}
+
+impl<'a> RecordContext<'a> {
+    #[inline(always)]
+    pub(crate) fn new(fields: &'a [(String, String)]) -> Self {
+        Self { seq: SEQ.fetch_add(1, Ordering::Relaxed), fields, thread: None }
+    }
+
+    /// Restore the context of a record captured in the logging thread.
+    #[inline(always)]
+    pub(crate) fn deferred(
+        seq: u64, fields: &'a [(String, String)], thread: &'a ThreadContext,
+    ) -> Self {
+        Self { seq, fields, thread: Some(thread) }
+    }
+
+    #[inline(always)]
+    pub(crate) fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+/// The thread-local context of a record, captured in the logging thread
+/// when the record is formatted by another thread.
+pub(crate) struct ThreadContext {
+    thread: thread::Thread,
+    scope: Option<Arc<str>>,
+}
+
+impl ThreadContext {
+    #[inline(always)]
+    pub(crate) fn capture() -> Self {
+        Self { thread: thread::current(), scope: crate::scope::current_scope() }
+    }
+}
+
+thread_local! {
+    static THREAD_NAME: Option<String> = thread::current().name().map(|name| name.to_string());
+}
+
+static PID: AtomicU32 = AtomicU32::new(0);
+
+#[cold]
+fn cache_pid() -> u32 {
+    static AT_FORK: Once = Once::new();
+    AT_FORK.call_once(|| {
+        extern "C" fn reset_pid() {
+            PID.store(0, Ordering::Relaxed);
+        }
+        unsafe {
+            libc::pthread_atfork(None, None, Some(reset_pid));
+        }
+    });
+    let pid = std::process::id();
+    PID.store(pid, Ordering::Relaxed);
+    pid
 }
 
 fn basename(path: &str) -> &str {
@@ -75,3 +232,13 @@ fn basename(path: &str) -> &str {
         None => path,
     }
 }
+
+fn get_hostname() -> String {
+    let mut buf = [0u8; 256];
+    let r = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if r != 0 {
+        return "<unknown>".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
@@ -8,7 +8,9 @@
 //!
 //! ## Features
 //!
-//! * Allow customize log format and time format. Refer to [LogFormat].
+//! * Allow customize log format and time format, in local time, UTC or a fixed offset. Refer to [LogFormat].
+//!
+//! * Mockable [Clock] for deterministic timestamps and rotation in tests.
 //!
 //! * Support subscribe log from **tracing**: (feature `tracing`). Refer to [tracing_bridge].
 //!
@@ -51,6 +53,9 @@
 //!
 //!       Refer to [Best practice with rstest](#best-practice-with-rstest).
 //!
+//!     + Isolate the logs of each test case when running in parallel, write to one file per test,
+//!       or only print the logs of failed tests. Refer to [scope].
+//!
 //! * Provides a [LogParser](crate::parser::LogParser) to work on your log files.
 //!
 //! ## Usage
@@ -79,6 +84,10 @@
 //!
 //!- `tracing`: Receive log from tracing
 //!
+//!- `zstd`: Compress rotated archives with zstd, see [rotation::Codec]
+//!
+//!- `io_uring`: Write the buffered file with io_uring on Linux, see [WriteMode]
+//!
 //! ## Recipes
 //!
 //! You can refer to various preset recipe in [recipe] module.
@@ -219,6 +228,8 @@
 //!
 //! cargo test -- --test-threads=1
 //!
+//! (If you want to run tests in parallel, use the sink in [scope] module instead.)
+//!
 //! /tmp/log_rstest.log will have this content:
 //!
 //! ``` text
@@ -246,10 +257,14 @@ extern crate enum_dispatch;
 mod buf_file_impl;
 mod config;
 mod console_impl;
+mod disk_guard;
 pub mod env;
+mod failover_impl;
 mod file_impl;
 mod formatter;
 mod log_impl;
+mod metrics;
+mod report;
 pub mod rotation;
 mod time;
 
@@ -265,16 +280,24 @@ pub mod ringfile;
 pub mod macros;
 pub mod parser;
 pub mod recipe;
+pub mod scope;
 
 pub mod filter;
 
 pub use self::buf_file_impl::*;
 pub use self::console_impl::*;
+pub use self::disk_guard::{DiskAction, DiskGuard};
+pub use self::failover_impl::*;
 pub use self::file_impl::*;
 pub use self::{
     config::*,
     formatter::FormatRecord,
     log_impl::{get_global_logger, setup_log, GlobalLogger},
+    metrics::{
+        prometheus_text, Histogram, MetricsExport, MetricsTarget, SinkStats, LATENCY_BUCKETS,
+    },
+    report::{ErrorHandler, SinkError, SinkErrorKind},
+    time::{Clock, ManualClock, TimeZone},
 };
 pub use captains_log_helper::logfn;
 
@@ -1,5 +1,11 @@
 use crate::{buf_file_impl::LogSinkBufFile, console_impl::LogSinkConsole, file_impl::LogSinkFile};
-use crate::{config::Builder, time::Timer};
+use crate::{
+    config::Builder,
+    formatter::RecordContext,
+    metrics::{self, prometheus_text, SinkStats},
+    report::{self, report_error, report_info, SinkErrorKind},
+    time::{Clock, Timer},
+};
 use arc_swap::ArcSwap;
 use backtrace::Backtrace;
 use signal_hook::iterator::Signals;
@@ -25,11 +31,41 @@ pub(crate) trait LogSinkTrait {
 
     fn reopen(&self) -> std::io::Result<()>;
 
-    fn log(&self, now: &Timer, r: &log::Record);
+    fn log(&self, now: &Timer, ctx: &RecordContext, r: &log::Record);
 
     fn flush(&self);
+
+    /// Called when a [LogScope](crate::scope::LogScope) ends
+    fn scope_end(&self, _scope: &Arc<str>, _failed: bool) {}
+
+    /// Push the counters of the sink, wrapper sinks push the inner ones.
+    fn stats(&self, _out: &mut Vec<SinkStats>) {}
+
+    /// False after failing to write, cheap to call for each record.
+    fn healthy(&self) -> bool {
+        true
+    }
+
+    /// Check the health (for example the file is removed) and try to recover,
+    /// called periodically by [LogFailover](crate::LogFailover).
+    fn probe(&self) -> bool {
+        self.healthy()
+    }
+
+    /// Write the lines already formatted by another sink, which failed to write them.
+    fn write_formatted(&self, _buf: &[u8]) {}
+
+    /// Set the sink to take over the lines failed to write in the background (and the lines
+    /// routed by [DiskAction::Fallback](crate::DiskAction::Fallback)), called by
+    /// [LogFailover](crate::LogFailover) with the secondary. Returns false if the lines failed
+    /// to write are not handed over.
+    fn set_spill(&self, _sink: Arc<LogSink>) -> bool {
+        false
+    }
 }
 
+// The sinks are built once on setup, inline to avoid the indirection on log()
+#[allow(clippy::large_enum_variant)]
 #[enum_dispatch(LogSinkTrait)]
 pub enum LogSink {
     File(LogSinkFile),
@@ -39,6 +75,8 @@ pub enum LogSink {
     Syslog(crate::syslog::LogSinkSyslog),
     #[cfg(feature = "ringfile")]
     RingFile(crate::ringfile::LogSinkRingFile),
+    PerTest(crate::scope::LogSinkPerTest),
+    Failover(crate::failover_impl::LogSinkFailover),
 }
 
 struct GlobalLoggerStatic {
@@ -112,12 +150,14 @@ impl GlobalLoggerStatic {
     /// Return Ok(false) when reinit, Ok(true) when first init, Err for error
     fn try_setup(&'static self, builder: &Builder) -> Result<bool, Error> {
         let _guard = self.lock();
+        report::setup(builder.error_handler.clone(), builder.quiet);
+        metrics::setup(builder.metrics_export.as_ref());
         let res = { self.get_logger().check_the_same(builder) };
         match res {
             Some(true) => {
                 // checksum is the same
                 if let Err(e) = self.get_logger().open() {
-                    eprintln!("failed to open log sink: {:?}", e);
+                    report_error(SinkErrorKind::Open, "", &e);
                     return Err(e);
                 }
                 // reset the log level
@@ -128,12 +168,12 @@ impl GlobalLoggerStatic {
                 // checksum is not the same
                 if !builder.dynamic {
                     let e = Error::other("log config differs but dynamic=false");
-                    eprintln!("{:?}", e);
+                    report_error(SinkErrorKind::Setup, "", &e);
                     return Err(e);
                 }
                 let logger = self.get_logger();
                 if let Err(e) = logger.reinit(builder) {
-                    eprintln!("{:?}", e);
+                    report_error(SinkErrorKind::Setup, "", &e);
                     return Err(e);
                 }
                 // reset the log level
@@ -177,8 +217,9 @@ pub fn setup_log(builder: Builder) -> Result<&'static GlobalLogger, Error> {
         let logger = GLOBAL_LOGGER.get_logger();
         // Set logger can only be called once
         if let Err(e) = log::set_logger(logger) {
-            eprintln!("log::set_logger return error: {:?}", e);
-            return Err(Error::other(format!("log::set_logger() failed: {:?}", e)));
+            let e = Error::other(format!("log::set_logger() failed: {:?}", e));
+            report_error(SinkErrorKind::Setup, "", &e);
+            return Err(e);
         }
         log::set_max_level(builder.get_max_level());
         if builder.panic_hook {
@@ -220,10 +261,42 @@ pub struct GlobalLogger {
     tracing_inited: AtomicBool,
 }
 
+/// The sinks and settings swapped together on reinit
+struct LogSinks {
+    sinks: Vec<LogSink>,
+    clock: Clock,
+    fields: Vec<(String, String)>,
+}
+
+impl std::ops::Deref for LogSinks {
+    type Target = Vec<LogSink>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.sinks
+    }
+}
+
+impl LogSinks {
+    #[inline]
+    fn new(sinks: Vec<LogSink>, builder: &Builder) -> Self {
+        Self { sinks, clock: builder.clock.clone(), fields: builder.fields.clone() }
+    }
+
+    #[inline(always)]
+    fn log(&self, r: &log::Record) {
+        let now = Timer::new(&self.clock);
+        let ctx = RecordContext::new(&self.fields);
+        for sink in self.sinks.iter() {
+            sink.log(&now, &ctx, r);
+        }
+    }
+}
+
 enum LoggerInnerSink {
-    Once(Vec<LogSink>),
+    Once(LogSinks),
     // using ArcSwap has more cost
-    Dyn(ArcSwap<Vec<LogSink>>),
+    Dyn(ArcSwap<LogSinks>),
 }
 
 struct LoggerInner {
@@ -235,7 +308,7 @@ unsafe impl Sync for LoggerInner {}
 
 impl LoggerInner {
     #[inline]
-    fn new(dynamic: bool, sinks: Vec<LogSink>) -> Self {
+    fn new(dynamic: bool, sinks: LogSinks) -> Self {
         let sinks = if dynamic {
             LoggerInnerSink::Dyn(ArcSwap::new(Arc::new(sinks)))
         } else {
@@ -245,7 +318,7 @@ impl LoggerInner {
     }
 
     #[inline]
-    fn set(&self, sinks: Vec<LogSink>) -> std::io::Result<()> {
+    fn set(&self, sinks: LogSinks) -> std::io::Result<()> {
         match &self.sinks {
             LoggerInnerSink::Once(_) => {
                 let e = Error::other("previous logger does not init with dynamic=true");
@@ -261,12 +334,12 @@ impl LoggerInner {
 
 impl GlobalLogger {
     fn listener_for_signal(&self, signals: Vec<i32>) {
-        println!("signal_listener started");
+        report_info(format_args!("signal_listener started"));
         let mut signals = Signals::new(&signals).unwrap();
         for __sig in signals.forever() {
             let _ = self.reopen();
         }
-        println!("signal_listener exit");
+        report_info(format_args!("signal_listener exit"));
     }
 
     /// On program/test Initialize
@@ -286,7 +359,7 @@ impl GlobalLogger {
                 }
             }
         }
-        println!("log sinks opened");
+        report_info(format_args!("log sinks opened"));
         Ok(())
     }
 
@@ -307,10 +380,77 @@ impl GlobalLogger {
                 }
             }
         }
-        println!("log sinks re-opened");
+        report_info(format_args!("log sinks re-opened"));
         Ok(())
     }
 
+    /// The total number of records dropped by the sinks (of current config) when the queue is
+    /// full, see [Backpressure](crate::Backpressure).
+    pub fn dropped(&self) -> u64 {
+        self.metrics().iter().map(|st| st.dropped).sum()
+    }
+
+    /// The counters of the sinks (of current config), see [SinkStats].
+    ///
+    /// [LogRawFile](crate::LogRawFile), [LogBufFile](crate::LogBufFile),
+    /// [LogConsole](crate::LogConsole) and [Syslog](crate::syslog::Syslog) are counted,
+    /// [LogFailover](crate::LogFailover) reports both of the inner sinks.
+    pub fn metrics(&self) -> Vec<SinkStats> {
+        let mut stats = Vec::new();
+        if let Some(inner) = self.inner.as_ref() {
+            match &inner.sinks {
+                LoggerInnerSink::Once(inner) => {
+                    for sink in inner.iter() {
+                        sink.stats(&mut stats);
+                    }
+                }
+                LoggerInnerSink::Dyn(inner) => {
+                    let sinks = inner.load();
+                    for sink in sinks.iter() {
+                        sink.stats(&mut stats);
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// The [metrics()](GlobalLogger::metrics()) in Prometheus text format,
+    /// see also [Builder::export_metrics()].
+    pub fn metrics_prometheus(&self) -> String {
+        prometheus_text(&self.metrics())
+    }
+
+    /// The health of each sink (of current config), in the order of [Builder::add_sink()].
+    ///
+    /// A sink is unhealthy after failing to open or write, and becomes healthy again when
+    /// recovered (for example on reopen). The error is passed to [Builder::on_error()].
+    pub fn health(&self) -> Vec<bool> {
+        let mut health = Vec::new();
+        if let Some(inner) = self.inner.as_ref() {
+            match &inner.sinks {
+                LoggerInnerSink::Once(inner) => {
+                    for sink in inner.iter() {
+                        health.push(sink.healthy());
+                    }
+                }
+                LoggerInnerSink::Dyn(inner) => {
+                    let sinks = inner.load();
+                    for sink in sinks.iter() {
+                        health.push(sink.healthy());
+                    }
+                }
+            }
+        }
+        health
+    }
+
+    /// True when all the sinks are healthy, see [GlobalLogger::health()]
+    #[inline]
+    pub fn healthy(&self) -> bool {
+        !self.health().contains(&false)
+    }
+
     /// Return Some(true) to skip, Some(false) to reinit, None to init
     #[inline]
     fn check_the_same(&self, builder: &Builder) -> Option<bool> {
@@ -324,7 +464,7 @@ impl GlobalLogger {
     fn reinit(&self, builder: &Builder) -> std::io::Result<()> {
         let sinks = builder.build_sinks()?;
         if let Some(inner) = self.inner.as_ref() {
-            inner.set(sinks)?;
+            inner.set(LogSinks::new(sinks, builder))?;
             self.config_checksum.store(builder.cal_checksum(), Ordering::Release);
         } else {
             unreachable!();
@@ -335,18 +475,38 @@ impl GlobalLogger {
     fn init(&mut self, builder: &Builder) -> std::io::Result<()> {
         let sinks = builder.build_sinks()?;
         assert!(self.inner.is_none());
+        let sinks = LogSinks::new(sinks, builder);
         self.inner.replace(LoggerInner::new(builder.dynamic, sinks));
         self.config_checksum.store(builder.cal_checksum(), Ordering::Release);
         Ok(())
     }
 
+    /// Notify the sinks that a scope ends
+    pub(crate) fn scope_end(&self, scope: &Arc<str>, failed: bool) {
+        if let Some(inner) = self.inner.as_ref() {
+            match &inner.sinks {
+                LoggerInnerSink::Once(inner) => {
+                    for sink in inner.iter() {
+                        sink.scope_end(scope, failed);
+                    }
+                }
+                LoggerInnerSink::Dyn(inner) => {
+                    let sinks = inner.load();
+                    for sink in sinks.iter() {
+                        sink.scope_end(scope, failed);
+                    }
+                }
+            }
+        }
+    }
+
     #[cfg(feature = "tracing")]
     #[inline]
     fn init_tracing_global(&'static self) -> Result<(), Error> {
         let dist = self.tracing_dispatch::<TracingText>()?;
         if let Err(_) = dispatcher::set_global_default(dist) {
             let e = Error::other("tracing global dispatcher already exists");
-            eprintln!("{:?}", e);
+            report_error(SinkErrorKind::Setup, "", &e);
             return Err(e);
         }
         self.tracing_inited.store(true, Ordering::SeqCst);
@@ -368,7 +528,7 @@ impl GlobalLogger {
     ) -> std::io::Result<CaptainsLogLayer<F>> {
         if self.tracing_inited.load(Ordering::SeqCst) {
             let e = Error::other("global tracing dispatcher exists");
-            eprintln!("{:?}", e);
+            report_error(SinkErrorKind::Setup, "", &e);
             return Err(e);
         }
         return Ok(CaptainsLogLayer::<F>::new(self));
@@ -387,7 +547,7 @@ impl GlobalLogger {
     pub fn tracing_dispatch<F: TracingFormatter>(&'static self) -> std::io::Result<Dispatch> {
         if self.tracing_inited.load(Ordering::SeqCst) {
             let e = Error::other("global tracing dispatcher exists");
-            eprintln!("{:?}", e);
+            report_error(SinkErrorKind::Setup, "", &e);
             return Err(e);
         }
         return Ok(Dispatch::new(
@@ -404,19 +564,13 @@ impl log::Log for GlobalLogger {
 
     #[inline(always)]
     fn log(&self, r: &log::Record) {
-        let now = Timer::new();
         if let Some(inner) = self.inner.as_ref() {
             match &inner.sinks {
                 LoggerInnerSink::Once(inner) => {
-                    for sink in inner.iter() {
-                        sink.log(&now, r);
-                    }
+                    inner.log(r);
                 }
                 LoggerInnerSink::Dyn(inner) => {
-                    let sinks = inner.load();
-                    for sink in sinks.iter() {
-                        sink.log(&now, r);
-                    }
+                    inner.load().log(r);
                 }
             }
         }
@@ -453,6 +607,7 @@ static GLOBAL_LOGGER: GlobalLoggerStatic = GlobalLoggerStatic::new();
 /// log handle for panic hook
 #[doc(hidden)]
 pub fn log_panic(info: &std::panic::PanicHookInfo) {
+    crate::scope::mark_failed();
     let bt = Backtrace::new();
     let mut record = log::Record::builder();
     record.level(log::Level::Error);
@@ -0,0 +1,260 @@
+use crate::{
+    log_impl::get_global_logger,
+    report::{report_error, SinkErrorKind},
+};
+use arc_swap::ArcSwapOption;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The upper bounds (in seconds) of the buckets of [SinkStats::flush_latency]
+pub const LATENCY_BUCKETS: [f64; 6] = [0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0];
+
+/// Snapshot of the counters of a log sink, returned by
+/// [GlobalLogger::metrics()](crate::GlobalLogger::metrics()).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SinkStats {
+    /// The file path, syslog server, or console target
+    pub name: String,
+    /// The records accepted by the level of the sink
+    pub records: u64,
+    pub bytes: u64,
+    /// The records dropped when the queue is full (see [Backpressure](crate::Backpressure)),
+    /// or given up by syslog after the timeout.
+    pub dropped: u64,
+    pub write_errors: u64,
+    pub rotations: u64,
+    /// The records waiting in the queue of the background thread
+    pub queue_depth: u64,
+    /// The time of each write to the file (a record for LogRawFile, or a batch for LogBufFile),
+    /// or a message to syslog.
+    ///
+    /// Only LogBufFile is timed by default, the others are timed when
+    /// [Builder::metrics_latency()](crate::Builder::metrics_latency()) or
+    /// [Builder::export_metrics()](crate::Builder::export_metrics()) is set.
+    pub flush_latency: Histogram,
+}
+
+/// Cumulative histogram as Prometheus
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// The number of observations not greater than the bounds of [LATENCY_BUCKETS]
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    /// In seconds
+    pub sum: f64,
+}
+
+/// The counters updated by the log sink, and its background thread.
+#[derive(Default)]
+pub(crate) struct SinkMetrics {
+    records: AtomicU64,
+    bytes: AtomicU64,
+    dropped: AtomicU64,
+    write_errors: AtomicU64,
+    rotations: AtomicU64,
+    /// The last one is +Inf
+    latency: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_sum_nanos: AtomicU64,
+    /// Observe the latency of writes
+    timed: bool,
+}
+
+impl SinkMetrics {
+    pub(crate) fn new(timed: bool) -> Self {
+        Self { timed, ..Default::default() }
+    }
+
+    /// Start timing a write, return None when the latency is not observed.
+    #[inline(always)]
+    pub(crate) fn start(&self) -> Option<Instant> {
+        if self.timed {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_accept(&self) {
+        self.records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_write(&self, bytes: usize, start: Option<Instant>) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        let elapsed = match start {
+            Some(start) => start.elapsed(),
+            None => return,
+        };
+        let secs = elapsed.as_secs_f64();
+        let i = LATENCY_BUCKETS.iter().position(|b| secs <= *b).unwrap_or(LATENCY_BUCKETS.len());
+        self.latency[i].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_rotate(&self) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn snapshot(&self, name: String, queue_depth: u64) -> SinkStats {
+        let mut buckets = Vec::with_capacity(LATENCY_BUCKETS.len());
+        let mut count = 0;
+        for (i, n) in self.latency.iter().enumerate() {
+            count += n.load(Ordering::Relaxed);
+            if i < LATENCY_BUCKETS.len() {
+                buckets.push(count);
+            }
+        }
+        SinkStats {
+            name,
+            records: self.records.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            dropped: self.dropped(),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            rotations: self.rotations.load(Ordering::Relaxed),
+            queue_depth,
+            flush_latency: Histogram {
+                buckets,
+                count,
+                sum: self.latency_sum_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+            },
+        }
+    }
+}
+
+/// Format the stats in Prometheus text format, labeled with `sink="<name>"`.
+pub fn prometheus_text(stats: &[SinkStats]) -> String {
+    let mut s = String::new();
+    let counters: [(&str, &str, fn(&SinkStats) -> u64); 6] = [
+        ("records_total", "Records accepted by the log sink", |st| st.records),
+        ("bytes_total", "Bytes written by the log sink", |st| st.bytes),
+        ("dropped_total", "Records dropped by the log sink", |st| st.dropped),
+        ("write_errors_total", "Errors writing the log sink", |st| st.write_errors),
+        ("rotations_total", "Rotations of the log file", |st| st.rotations),
+        ("queue_depth", "Records waiting in the queue", |st| st.queue_depth),
+    ];
+    for (name, help, f) in counters.iter() {
+        let t = if *name == "queue_depth" { "gauge" } else { "counter" };
+        let _ = writeln!(s, "# HELP captains_log_{} {}", name, help);
+        let _ = writeln!(s, "# TYPE captains_log_{} {}", name, t);
+        for st in stats {
+            let _ = writeln!(s, "captains_log_{}{{sink=\"{}\"}} {}", name, escape(&st.name), f(st));
+        }
+    }
+    let name = "captains_log_flush_latency_seconds";
+    let _ = writeln!(s, "# HELP {} Latency of writing the log sink", name);
+    let _ = writeln!(s, "# TYPE {} histogram", name);
+    for st in stats {
+        let sink = escape(&st.name);
+        let h = &st.flush_latency;
+        for (bound, n) in LATENCY_BUCKETS.iter().zip(h.buckets.iter()) {
+            let _ = writeln!(s, "{}_bucket{{sink=\"{}\",le=\"{}\"}} {}", name, sink, bound, n);
+        }
+        let _ = writeln!(s, "{}_bucket{{sink=\"{}\",le=\"+Inf\"}} {}", name, sink, h.count);
+        let _ = writeln!(s, "{}_sum{{sink=\"{}\"}} {}", name, sink, h.sum);
+        let _ = writeln!(s, "{}_count{{sink=\"{}\"}} {}", name, sink, h.count);
+    }
+    s
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Where to export the metrics in Prometheus text format
+#[derive(Clone)]
+pub enum MetricsTarget {
+    /// Write to a temporary file and rename, for the textfile collector of node_exporter.
+    File(PathBuf),
+    Callback(Arc<dyn Fn(&str) + Send + Sync + 'static>),
+}
+
+/// Export the metrics of [GlobalLogger](crate::GlobalLogger) periodically from a background
+/// thread, configured by [Builder::export_metrics()](crate::Builder::export_metrics()).
+///
+/// # Example
+///
+/// ``` rust
+/// use captains_log::*;
+/// use std::time::Duration;
+/// let export = MetricsExport::file("/tmp/captains_log.prom", Duration::from_secs(10));
+/// let builder = recipe::raw_file_logger("/tmp/test.log", Level::Info).export_metrics(export);
+/// ```
+#[derive(Clone)]
+pub struct MetricsExport {
+    pub interval: Duration,
+    pub target: MetricsTarget,
+}
+
+impl MetricsExport {
+    pub fn file<P: Into<PathBuf>>(path: P, interval: Duration) -> Self {
+        Self { interval, target: MetricsTarget::File(path.into()) }
+    }
+
+    pub fn callback<F: Fn(&str) + Send + Sync + 'static>(f: F, interval: Duration) -> Self {
+        Self { interval, target: MetricsTarget::Callback(Arc::new(f)) }
+    }
+
+    fn export(&self, text: &str) {
+        match &self.target {
+            MetricsTarget::File(path) => {
+                let mut tmp = path.as_os_str().to_os_string();
+                tmp.push(".tmp");
+                if let Err(e) = std::fs::write(&tmp, text).and_then(|_| std::fs::rename(&tmp, path))
+                {
+                    report_error(SinkErrorKind::Write, &path.to_string_lossy(), &e);
+                }
+            }
+            MetricsTarget::Callback(f) => f(text),
+        }
+    }
+}
+
+static METRICS_EXPORT: ArcSwapOption<MetricsExport> = ArcSwapOption::const_empty();
+
+static EXPORTER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Install the export from builder on each setup, the thread is started once.
+pub(crate) fn setup(export: Option<&MetricsExport>) {
+    METRICS_EXPORT.store(export.map(|e| Arc::new(e.clone())));
+    if export.is_some() && !EXPORTER_STARTED.swap(true, Ordering::SeqCst) {
+        thread::spawn(exporter);
+    }
+}
+
+fn exporter() {
+    loop {
+        let interval = match METRICS_EXPORT.load().as_ref() {
+            Some(export) => export.interval,
+            None => Duration::from_secs(1),
+        };
+        thread::sleep(interval);
+        if let Some(export) = METRICS_EXPORT.load_full() {
+            if let Some(logger) = get_global_logger() {
+                export.export(&logger.metrics_prometheus());
+            }
+        }
+    }
+}
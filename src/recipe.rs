@@ -23,6 +23,21 @@ pub const LOG_FORMAT_THREADED_DEBUG: LogFormat =
 /// [{time}][{level}] {msg}
 pub const LOG_FORMAT_PROD: LogFormat = LogFormat::new(DEFAULT_TIME, prod_format_f);
 
+/// RFC3339 time with microseconds and offset, for example `2025-06-11T06:33:08.089090+00:00`
+pub const RFC3339_TIME: &str = "%Y-%m-%dT%H:%M:%S%.6f%:z";
+
+/// [{time}][{level}][{file}:{line}] {msg}, with RFC3339 time in UTC
+pub const LOG_FORMAT_RFC3339_UTC: LogFormat =
+    LogFormat::new(RFC3339_TIME, debug_format_f).timezone(TimeZone::Utc);
+
+/// [{epoch millis}][{level}][{file}:{line}] {msg}
+pub const LOG_FORMAT_EPOCH_MILLIS: LogFormat = LogFormat::new("", epoch_millis_format_f);
+
+/// One JSON object per line, with RFC3339 time in UTC and the static fields from [Builder::with_field()]
+/// under `"fields"`
+pub const LOG_FORMAT_JSON: LogFormat =
+    LogFormat::new(RFC3339_TIME, json_format_f).timezone(TimeZone::Utc);
+
 /// formatter function: [{time}][{level}][{file}:{line}] {msg}
 pub fn debug_format_f(r: FormatRecord) -> String {
     let time = r.time();
@@ -52,6 +67,56 @@ pub fn prod_format_f(r: FormatRecord) -> String {
     format!("[{time}][{level}] {msg}\n").to_string()
 }
 
+/// formatter function: [{epoch millis}][{level}][{file}:{line}] {msg}
+pub fn epoch_millis_format_f(r: FormatRecord) -> String {
+    let time = r.timestamp_millis();
+    let level = r.level();
+    let file = r.file();
+    let line = r.line();
+    let msg = r.msg();
+    format!("[{time}][{level}][{file}:{line}] {msg}\n").to_string()
+}
+
+/// formatter function:
+/// {"time":"{time}","level":"{level}","file":"{file}","line":{line},"msg":"{msg}","fields":{fields}}
+///
+/// The fields are nested so they never collide with the keys above, omitted when there is none.
+pub fn json_format_f(r: FormatRecord) -> String {
+    let time = r.time();
+    let level = r.level();
+    let file = json_escape(r.file());
+    let line = r.line();
+    let msg = json_escape(&r.msg().to_string());
+    let mut s = format!(
+        r#"{{"time":"{time}","level":"{level}","file":"{file}","line":{line},"msg":"{msg}""#
+    );
+    for (i, (k, v)) in r.fields().iter().enumerate() {
+        s.push_str(if i == 0 { r#","fields":{"# } else { "," });
+        s.push_str(&format!(r#""{}":"{}""#, json_escape(k), json_escape(v)));
+    }
+    if !r.fields().is_empty() {
+        s.push('}');
+    }
+    s.push_str("}\n");
+    s
+}
+
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 pub fn console_logger(target: ConsoleTarget, max_level: Level) -> Builder {
     let console_config = LogConsole::new(target, max_level, LOG_FORMAT_DEBUG);
     return Builder::default().add_sink(console_config);
@@ -122,9 +187,10 @@ pub fn raw_file_logger_custom<P: Into<PathBuf>>(
 ) -> Builder {
     let format = LogFormat::new(time_fmt, format_func);
     let _file_path = file_path.into();
-    let p = path::absolute(&_file_path).expect("path convert to absolute");
-    let dir = p.parent().unwrap();
-    let file_name = Path::new(p.file_name().unwrap());
+    let p = path::absolute(&_file_path).unwrap_or(_file_path);
+    // An invalid path is reported by build()
+    let dir = p.parent().unwrap_or(Path::new(""));
+    let file_name = Path::new(p.file_name().unwrap_or_default());
     let file = LogRawFile::new(dir, file_name, max_level, format);
     return Builder::default().signal(signal_hook::consts::SIGUSR1).add_sink(file);
 }
@@ -140,6 +206,28 @@ pub fn raw_file_logger<P: Into<PathBuf>>(file_path: P, max_level: Level) -> Buil
     raw_file_logger_custom(file_path, max_level, DEFAULT_TIME, debug_format_f)
 }
 
+/// Setup one log file, capable of self rotation coordinated among processes.
+///
+/// See the source for details.
+///
+/// # Arguments:
+///
+/// - `file_path`: The type of file_path can be &str / String / &OsStr / OsString / Path / PathBuf
+///
+/// - `rotation`: rotation and archive strategy
+pub fn raw_rotated_file_logger<P: Into<PathBuf>>(
+    file_path: P, max_level: Level, rotation: crate::rotation::Rotation,
+) -> Builder {
+    let format = LogFormat::new(DEFAULT_TIME, debug_format_f);
+    let _file_path = file_path.into();
+    let p = path::absolute(&_file_path).unwrap_or(_file_path);
+    // An invalid path is reported by build()
+    let dir = p.parent().unwrap_or(Path::new(""));
+    let file_name = Path::new(p.file_name().unwrap_or_default());
+    let file = LogRawFile::new(dir, file_name, max_level, format).rotation(rotation);
+    return Builder::default().add_sink(file);
+}
+
 /// Setup two log files.
 /// One as "{{name}}.log" for debug purpose, with file line to track problem.
 /// One as "{{name}}.log.wf" for error level log.
@@ -188,9 +276,10 @@ pub fn buffered_file_logger_custom<P: Into<PathBuf>>(
 ) -> Builder {
     let format = LogFormat::new(time_fmt, format_func);
     let _file_path = file_path.into();
-    let p = path::absolute(&_file_path).expect("path convert to absolute");
-    let dir = p.parent().unwrap();
-    let file_name = Path::new(p.file_name().unwrap());
+    let p = path::absolute(&_file_path).unwrap_or(_file_path);
+    // An invalid path is reported by build()
+    let dir = p.parent().unwrap_or(Path::new(""));
+    let file_name = Path::new(p.file_name().unwrap_or_default());
     let mut file = LogBufFile::new(dir, file_name, max_level, format, flush_millis);
     if let Some(ro) = rotate {
         file = file.rotation(ro);
@@ -231,6 +320,24 @@ pub fn buffered_rotated_file_logger<P: Into<PathBuf>>(
     )
 }
 
+/// Write the logs of each test case to `<dir>/<test name>.log`, with dynamic=true.
+/// Test function should be wrapped with `#[logfn]`. Refer to [scope] for details.
+///
+/// # Arguments:
+///
+/// - `dir`: directory of the log files, can be &str / String / &OsStr / OsString / Path / PathBuf.
+pub fn per_test_file_logger<P: Into<PathBuf>>(dir: P, max_level: Level) -> Builder {
+    let sink = scope::LogPerTest::dir(dir, max_level, LOG_FORMAT_DEBUG);
+    return Builder::default().add_sink(sink).test();
+}
+
+/// Keep the logs of each test case in memory, print them to stderr only when the test fails.
+/// With dynamic=true. Test function should be wrapped with `#[logfn]`. Refer to [scope] for details.
+pub fn per_test_capture_logger(max_level: Level) -> Builder {
+    let sink = scope::LogPerTest::on_failure(ConsoleTarget::Stderr, max_level, LOG_FORMAT_DEBUG);
+    return Builder::default().add_sink(sink).test();
+}
+
 /// Output to local syslog
 #[cfg(feature = "syslog")]
 #[cfg_attr(docsrs, doc(cfg(feature = "syslog")))]
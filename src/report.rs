@@ -0,0 +1,79 @@
+use arc_swap::ArcSwapOption;
+use std::fmt;
+use std::io;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Callback to receive the errors of log sinks, see [Builder::on_error()](crate::Builder::on_error())
+pub type ErrorHandler = Arc<dyn Fn(&SinkError) + Send + Sync + 'static>;
+
+/// What the log sink was doing when the error occurred
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SinkErrorKind {
+    /// Failed to setup the global logger
+    Setup,
+    /// Failed to build or open the sink
+    Open,
+    Write,
+    Flush,
+    /// fdatasync
+    Sync,
+    /// stat or statvfs
+    Stat,
+    /// flock on the lock file of rotation
+    Lock,
+    /// Rotate, compress, chmod or delete the archives
+    Rotate,
+    /// Connect to syslog server
+    Connect,
+    /// Dump the ring buffer
+    Dump,
+}
+
+/// An error occurred in log sinks, passed to the [ErrorHandler]
+#[derive(Debug)]
+pub struct SinkError<'a> {
+    pub kind: SinkErrorKind,
+    /// The file path or syslog server, empty for the errors of logger setup.
+    pub sink: &'a str,
+    pub error: &'a io::Error,
+}
+
+impl<'a> fmt::Display for SinkError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.sink.is_empty() {
+            write!(f, "captains_log: {:?} error: {}", self.kind, self.error)
+        } else {
+            write!(f, "captains_log: {:?} {} error: {}", self.kind, self.sink, self.error)
+        }
+    }
+}
+
+static ERROR_HANDLER: ArcSwapOption<ErrorHandler> = ArcSwapOption::const_empty();
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Install the settings from builder, on each setup.
+pub(crate) fn setup(handler: Option<ErrorHandler>, quiet: bool) {
+    ERROR_HANDLER.store(handler.map(Arc::new));
+    QUIET.store(quiet, Ordering::Release);
+}
+
+/// Pass the error to the handler, or print to stderr by default.
+pub(crate) fn report_error(kind: SinkErrorKind, sink: &str, error: &io::Error) {
+    let e = SinkError { kind, sink, error };
+    if let Some(handler) = ERROR_HANDLER.load().as_ref() {
+        (***handler)(&e);
+    } else {
+        eprintln!("{}", e);
+    }
+}
+
+/// Print the informational message to stdout, unless quiet.
+pub(crate) fn report_info(args: fmt::Arguments) {
+    if !QUIET.load(Ordering::Acquire) {
+        println!("{}", args);
+    }
+}
@@ -66,8 +66,10 @@
 //! note that thread_id is reused after thread exits.
 
 use crate::{
-    config::{LogFormat, SinkConfigBuild, SinkConfigTrait},
+    config::{Builder, LogFormat, SinkConfigBuild, SinkConfigTrait},
+    formatter::RecordContext,
     log_impl::{LogSink, LogSinkTrait},
+    report::{report_error, SinkErrorKind},
     time::Timer,
 };
 use log::*;
@@ -97,8 +99,8 @@ impl LogRingFile {
 }
 
 impl SinkConfigBuild for LogRingFile {
-    fn build(&self) -> LogSink {
-        LogSink::RingFile(LogSinkRingFile::new(self))
+    fn build(&self, _builder: &Builder) -> std::io::Result<LogSink> {
+        Ok(LogSink::RingFile(LogSinkRingFile::new(self)))
     }
 }
 
@@ -121,6 +123,7 @@ pub(crate) struct LogSinkRingFile {
     max_level: Level,
     formatter: LogFormat,
     ring: RingFile,
+    file_path: Box<Path>,
 }
 
 unsafe impl Send for LogSinkRingFile {}
@@ -132,6 +135,7 @@ impl LogSinkRingFile {
             max_level: config.level,
             formatter: config.format.clone(),
             ring: RingFile::new(config.buf_size, config.file_path.clone()),
+            file_path: config.file_path.clone(),
         }
     }
 
@@ -139,7 +143,7 @@ impl LogSinkRingFile {
         let mut f = stdout();
         let _ = f.write_all(b"RingFile: start dumping\n");
         if let Err(e) = self.ring.dump() {
-            eprintln!("RingFile: dump error {:?}", e);
+            report_error(SinkErrorKind::Dump, &self.file_path.to_string_lossy(), &e);
             return Err(e);
         }
         let _ = f.write_all(b"RingFile: dump complete\n");
@@ -161,9 +165,9 @@ impl LogSinkTrait for LogSinkRingFile {
     }
 
     #[inline(always)]
-    fn log(&self, now: &Timer, r: &Record) {
+    fn log(&self, now: &Timer, ctx: &RecordContext, r: &Record) {
         if r.level() <= self.max_level {
-            let content = self.formatter.process(now, r);
+            let content = self.formatter.process(now, ctx, r);
             self.ring.write(content);
         }
     }
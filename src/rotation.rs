@@ -12,17 +12,30 @@
 //!
 //! * Where to archive: Optional directory to move the file into.
 //!
+//! * [Rotation::rotate_on_start()]: Every run starts with a fresh file.
+//!
+//! * [Rotation::symlink()]: Write to a time-stamped file, with a symlink pointing to it (like cronolog).
+//!
 //! * How the cleanup the file:
 //!
 //!     - [Upkeep]
 //!
 //!     - [Rotation::compress_exclude]
 //!
+//!     - [Rotation::codec]: gzip with level, or zstd (with feature `zstd`)
+//!
+//! * Notify the application: [Rotation::on_archive()] and [Rotation::on_delete()], for example
+//!   to upload the archives to object storage.
+//!
+//! Rotation is supported by [LogBufFile](crate::LogBufFile) (single writer), and
+//! [LogRawFile](crate::LogRawFile) (multiple processes writing to the same file).
+//!
+use crate::file_impl::FileOptions;
+use crate::report::{report_error, SinkErrorKind};
+use crate::time::{Clock, TimeZone};
+use chrono::{DateTime, Datelike, NaiveDateTime, Offset, TimeZone as _, Timelike, Utc};
 use file_rotate::compression::Compression;
-use file_rotate::suffix::{
-    AppendCount, AppendTimestamp, DateFrom, FileLimit, Representation, SuffixScheme,
-};
-use file_rotate::SuffixInfo;
+use file_rotate::suffix::{AppendCount, Representation, SuffixScheme, TimestampSuffix};
 use flate2::write::GzEncoder;
 use parking_lot::Mutex;
 use std::cell::UnsafeCell;
@@ -30,40 +43,111 @@ use std::collections::BTreeSet;
 use std::fs::{self, File, OpenOptions};
 use std::io;
 use std::mem::transmute;
+use std::os::unix::prelude::*;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::thread;
 use std::time::{Duration, SystemTime};
 
-#[derive(Hash, Clone, Copy, PartialEq)]
+/// The interval of rotation by age.
+#[derive(Hash, Clone, Copy, PartialEq, Debug)]
 pub enum Age {
     Day,
     Hour,
+    Minute,
+    /// Aligned to Monday 00:00 with [Rotation::align()]
+    Week,
+    /// Calendar month, aligned to the first day 00:00 with [Rotation::align()]
+    Month,
+    /// Arbitrary interval (should not be zero),
+    /// aligned to multiples of the interval since UNIX epoch (in the timezone) with [Rotation::align()],
+    /// which requires whole seconds.
+    Every(Duration),
+}
+
+impl Age {
+    /// Shift the time by one period, forward or backward, Month is calculated in `tz`.
+    fn shift(&self, t: SystemTime, tz: TimeZone, forward: bool) -> SystemTime {
+        let d = match self {
+            Self::Minute => Duration::from_secs(60),
+            Self::Hour => Duration::from_secs(60 * 60),
+            Self::Day => Duration::from_secs(24 * 60 * 60),
+            Self::Week => Duration::from_secs(7 * 24 * 60 * 60),
+            Self::Every(d) => *d,
+            Self::Month => {
+                return match tz {
+                    TimeZone::Local => shift_month(&chrono::Local, t, forward),
+                    TimeZone::Utc => shift_month(&Utc, t, forward),
+                    TimeZone::Fixed(offset) => shift_month(&offset, t, forward),
+                };
+            }
+        };
+        if forward {
+            t + d
+        } else {
+            t - d
+        }
+    }
 }
 
 /// Define the time to rotate files.
 #[derive(Hash, Clone, Copy, PartialEq)]
 pub struct ByAge {
-    /// Rotate the file by day / hour.
+    /// Rotate the file by [Age].
     pub age_type: Age,
 
-    /// Similar to system's log-rotate,
+    /// Similar to system's log-rotate, the latest archive use the timestamp of one period ago.
     /// For Age::Day, the latest archive use yesterday's timestamp;
-    /// For Age::Hour, use last hour's timestamp.
+    /// For Age::Hour, use last hour's timestamp; For Age::Month, use last month's timestamp.
     pub use_last_time: bool,
 }
 
 /// Define how to cleanup old files
+///
+/// The policies work for both `file.<number>` and `file.<datetime>` archives.
+/// The age of a `file.<datetime>` archive is from its name, and the age of a `file.<number>`
+/// archive is from its mtime.
 #[derive(Hash, Clone, Copy, PartialEq)]
 pub enum Upkeep {
     /// Log file  older than the duration will be deleted.
     Age(chrono::TimeDelta),
     /// Only keeps the number of old logs.
     Count(usize),
+    /// Delete the oldest archives until the total size of the archives (of this log) is under the bytes.
+    Size(u64),
+    /// Delete the archives exceeding any of the limits.
+    ///
+    /// For example, keep 30 days but at most 20 GiB:
+    /// ``` rust
+    /// use captains_log::rotation::*;
+    /// let upkeep = Upkeep::Limits {
+    ///     max_age: Some(chrono::TimeDelta::days(30)),
+    ///     max_count: None,
+    ///     max_size: Some(20 << 30),
+    /// };
+    /// ```
+    Limits { max_age: Option<chrono::TimeDelta>, max_count: Option<usize>, max_size: Option<u64> },
     /// Does not delete any old logs.
     All,
 }
 
+impl Upkeep {
+    /// Return (max_age, max_count, max_size)
+    #[inline]
+    fn limits(&self) -> (Option<chrono::TimeDelta>, Option<usize>, Option<u64>) {
+        match *self {
+            Self::Age(age) => (Some(age), None, None),
+            Self::Count(count) => (None, Some(count), None),
+            Self::Size(size) => (None, None, Some(size)),
+            Self::Limits { max_age, max_count, max_size } => (max_age, max_count, max_size),
+            Self::All => (None, None, None),
+        }
+    }
+}
+
 /// Log rotation configuration.
 ///
 /// `by_age` and `by_size` can be configured at the same time, means log will be rotate when any of the conditions met.
@@ -73,7 +157,7 @@ pub struct Rotation {
     pub by_age: Option<ByAge>,
     pub by_size: Option<u64>,
 
-    /// If None, archive in `file.<number>` form, and Upkeep::Age will be ignore.
+    /// If None, archive in `file.<number>` form.
     ///
     /// If Some, archive in `file.<datetime>` form.
     pub time_fmt: Option<&'static str>,
@@ -88,6 +172,59 @@ pub struct Rotation {
     /// When Some(count), indicate how many uncompressed archived logs. When 0, all the archive logs are compressed.
     /// When None, do not compress archive logs;
     pub compress_exclude: Option<usize>,
+
+    /// The compression algorithm, default to gzip level 6.
+    pub codec: Codec,
+
+    /// Compress with idle I/O priority (Linux only), to reduce the impact on other disk I/O.
+    pub compress_idle_io: bool,
+
+    /// Called with the path of each archive after rename or compression, see [Rotation::on_archive()].
+    pub on_archive: Option<ArchiveHook>,
+
+    /// Called with the path of the archive before it's deleted by [Upkeep].
+    pub on_delete: Option<ArchiveHook>,
+
+    /// Archive the existing non-empty log on startup.
+    pub rotate_on_start: bool,
+
+    /// Write to `<file>.<time_fmt>`, and keep `<file>` as a symlink to it, see [Rotation::symlink()].
+    pub symlink: bool,
+
+    /// When Some, rotate `by_age` at the calendar boundary (midnight / top of the hour) in the
+    /// timezone, and the archive is named after the start of the period it covers,
+    /// see [Rotation::align()].
+    pub align: Option<TimeZone>,
+
+    /// Permissions and ownership of the archives and `archive_dir`, default to the same as the sink.
+    pub file_options: Option<FileOptions>,
+}
+
+/// A callback receiving the path of an archive, configured by [Rotation::on_archive()] and
+/// [Rotation::on_delete()].
+#[derive(Clone)]
+pub struct ArchiveHook(Arc<dyn Fn(&Path) + Send + Sync>);
+
+impl ArchiveHook {
+    pub fn new<F: Fn(&Path) + Send + Sync + 'static>(f: F) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// The panic of the hook is caught and reported, not to fail the cleanup thread.
+    #[inline]
+    fn call(&self, path: &Path) {
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| (self.0)(path)));
+        if r.is_err() {
+            let e = io::Error::other("archive hook panicked");
+            report_error(SinkErrorKind::Rotate, &path.to_string_lossy(), &e);
+        }
+    }
+}
+
+impl std::hash::Hash for ArchiveHook {
+    fn hash<H: std::hash::Hasher>(&self, hasher: &mut H) {
+        hasher.write_usize(Arc::as_ptr(&self.0) as *const () as usize);
+    }
 }
 
 impl Rotation {
@@ -107,6 +244,14 @@ impl Rotation {
             upkeep,
             archive_dir: None,
             compress_exclude: None,
+            codec: Codec::default(),
+            compress_idle_io: false,
+            on_archive: None,
+            on_delete: None,
+            rotate_on_start: false,
+            symlink: false,
+            align: None,
+            file_options: None,
         }
     }
 
@@ -118,7 +263,7 @@ impl Rotation {
     ///
     ///     - For Age::Day, the latest archive use yesterday's timestamp;
     ///
-    ///     - For Age::Hour, use last hour's timestamp.
+    ///     - For Age::Hour, use last hour's timestamp, and so on.
     ///
     /// - `time_fmt`: timestamp format of the archived files
     ///
@@ -134,6 +279,14 @@ impl Rotation {
             time_fmt: Some(time_fmt),
             upkeep,
             compress_exclude: None,
+            codec: Codec::default(),
+            compress_idle_io: false,
+            on_archive: None,
+            on_delete: None,
+            rotate_on_start: false,
+            symlink: false,
+            align: None,
+            file_options: None,
             archive_dir: None,
         }
     }
@@ -146,7 +299,7 @@ impl Rotation {
     ///
     ///     - For Age::Day, the latest archive use yesterday's timestamp;
     ///
-    ///     - For Age::Hour, use last hour's timestamp.
+    ///     - For Age::Hour, use last hour's timestamp, and so on.
     ///
     /// - `time_fmt`: timestamp format of the archived files
     ///
@@ -163,6 +316,14 @@ impl Rotation {
             time_fmt: Some(time_fmt),
             upkeep,
             compress_exclude: None,
+            codec: Codec::default(),
+            compress_idle_io: false,
+            on_archive: None,
+            on_delete: None,
+            rotate_on_start: false,
+            symlink: false,
+            align: None,
+            file_options: None,
             archive_dir: None,
         }
     }
@@ -175,112 +336,322 @@ impl Rotation {
         self
     }
 
+    /// Align the rotation by age to calendar boundaries in the timezone, for example midnight
+    /// for Age::Day, so that a daily file always contains exactly one day.
+    ///
+    /// Without it, the file is rotated when it has been written for a whole day / hour since
+    /// created. After restart, the creation time is from the birth time of the file (statx), or
+    /// recorded in xattr when not supported by the filesystem.
+    ///
+    /// When aligned, the archive is named after the start of the period it covers
+    /// (`use_last_time` is ignored), so the names are predictable regardless of restarts.
+    /// Files written in a previous period are rotated on startup.
+    ///
+    /// It requires `by_age`, otherwise the sink fails to build.
+    ///
+    /// # Example
+    ///
+    /// ``` rust
+    /// use captains_log::{*, rotation::*};
+    /// let rotation = Rotation::by_age(Age::Day, true, "%Y%m%d", None).align(TimeZone::Utc);
+    /// ```
+    pub fn align(mut self, tz: TimeZone) -> Self {
+        self.align = Some(tz);
+        self
+    }
+
+    /// Set the compression algorithm for [Rotation::compress_exclude].
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Compress with idle I/O priority (Linux only), to reduce the impact on other disk I/O.
+    pub fn compress_idle_io(mut self) -> Self {
+        self.compress_idle_io = true;
+        self
+    }
+
+    /// Replace the policy to cleanup the old files.
+    pub fn upkeep(mut self, upkeep: Upkeep) -> Self {
+        self.upkeep = upkeep;
+        self
+    }
+
+    /// Call `f` with the path of each archive, after rename, unless it's compressed right away
+    /// with `compress_exclude(0)`. The archives kept uncompressed by [Rotation::compress_exclude]
+    /// are notified again with the compressed path, once compressed by the later rotations.
+    /// Can be used to upload the archive to object storage, or to notify a log shipper.
+    ///
+    /// The hook runs in the background cleanup thread, but the next rotation waits for it,
+    /// so it should not block for too long. A panic in the hook is caught and reported as
+    /// [SinkErrorKind::Rotate].
+    ///
+    /// NOTE: `file.<number>` archives are renamed on each rotation, prefer `file.<datetime>`
+    /// form (by_age) if the path is to be recorded.
+    ///
+    /// # Example
+    ///
+    /// ``` rust
+    /// use captains_log::rotation::*;
+    /// let rotation = Rotation::by_age(Age::Day, true, "%Y%m%d", None)
+    ///     .compress_exclude(0)
+    ///     .on_archive(|path| println!("archived {}", path.display()));
+    /// ```
+    pub fn on_archive<F: Fn(&Path) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.on_archive.replace(ArchiveHook::new(f));
+        self
+    }
+
+    /// Call `f` with the path of each archive before it's deleted by [Upkeep].
+    pub fn on_delete<F: Fn(&Path) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.on_delete.replace(ArchiveHook::new(f));
+        self
+    }
+
+    /// Archive the existing log (when not empty) on startup, so that every run starts with a
+    /// fresh file.
+    ///
+    /// NOTE: For [LogRawFile](crate::LogRawFile) shared by multiple processes, the start of
+    /// any process rotates the file.
+    pub fn rotate_on_start(mut self) -> Self {
+        self.rotate_on_start = true;
+        self
+    }
+
+    /// Write to a time-stamped file `<file>.<time_fmt>` (named after the time it's created, or the
+    /// start of the period with [Rotation::align()]), and keep `<file>` as a symlink pointing to
+    /// the active one, similar to cronolog.
+    ///
+    /// On rotation, the symlink is switched to a new file, and the old one becomes the archive
+    /// without renaming (moved when `archive_dir` is configured), so the names of the archives are
+    /// stable for tail and shippers. `use_last_time` is ignored.
+    ///
+    /// Only available for [Rotation::by_age()] and [Rotation::by_age_and_size()].
+    /// An existing regular file at `<file>` is moved to a time-stamped file on startup.
+    ///
+    /// # Example
+    ///
+    /// ``` rust
+    /// use captains_log::rotation::*;
+    /// // app.log -> app.log.20250101
+    /// let rotation = Rotation::by_age(Age::Day, false, "%Y%m%d", None).symlink();
+    /// ```
+    pub fn symlink(mut self) -> Self {
+        self.symlink = true;
+        self
+    }
+
+    /// Set the permissions and ownership of the archives (including the compressed ones) and
+    /// `archive_dir`, when not set, the same as
+    /// [LogRawFile::file_options()](crate::LogRawFile::file_options()) or
+    /// [LogBufFile::file_options()](crate::LogBufFile::file_options()).
+    pub fn file_options(mut self, opts: FileOptions) -> Self {
+        self.file_options = Some(opts);
+        self
+    }
+
     /// Move the old logs into an `archive_dir`.
     pub fn archive_dir<P: Into<PathBuf>>(mut self, archive_dir: P) -> Self {
         self.archive_dir.replace(archive_dir.into());
         self
     }
 
-    pub(crate) fn build(&self, file_path: &Path) -> LogRotate {
-        assert!(
-            self.by_age.is_some() || self.by_size.is_some(),
-            "by_age and by_size can not be both None"
-        );
+    /// `lock_path` is held to cleanup the incomplete archives, when multiple processes share the
+    /// file.
+    pub(crate) fn build(
+        &self, file_path: &Path, clock: &Clock, file_options: &FileOptions,
+        lock_path: Option<&Path>,
+    ) -> io::Result<LogRotate> {
+        if self.by_age.is_none() && self.by_size.is_none() {
+            return Err(invalid_input("by_age and by_size can not be both None"));
+        }
         let archive_dir = if let Some(_dir) = &self.archive_dir {
             _dir.clone()
         } else {
-            // TODO FIXME
-            file_path.parent().unwrap().to_path_buf()
+            match file_path.parent() {
+                Some(dir) => dir.to_path_buf(),
+                None => return Err(invalid_input("log file has no parent dir")),
+            }
         };
+        if self.symlink && self.time_fmt.is_none() {
+            return Err(invalid_input("symlink requires time_fmt"));
+        }
+        if self.align.is_some() && self.by_age.is_none() {
+            return Err(invalid_input("align requires by_age"));
+        }
+        if self.symlink && file_options.nofollow {
+            return Err(invalid_input("symlink is not compatible with nofollow"));
+        }
+        let file_options = self.file_options.unwrap_or(*file_options);
         let mut size = None;
         let mut age = None;
-        let mut date_from = DateFrom::Now;
+        let mut last_time = None;
         if let Some(by_age) = &self.by_age {
-            if by_age.use_last_time {
-                match by_age.age_type {
-                    Age::Hour => {
-                        date_from = DateFrom::DateHourAgo;
-                    }
-                    Age::Day => {
-                        date_from = DateFrom::DateYesterday;
-                    }
+            if let Age::Every(d) = by_age.age_type {
+                if d.is_zero() {
+                    return Err(invalid_input("Age::Every should not be zero"));
+                }
+                if self.align.is_some() && d.subsec_nanos() != 0 {
+                    return Err(invalid_input("aligned Age::Every should be whole seconds"));
                 }
             }
-            age.replace(LimiterAge::new(by_age.age_type));
+            if by_age.use_last_time {
+                last_time = Some(by_age.age_type);
+            }
+            age.replace(LimiterAge::new(by_age.age_type, self.align, clock.clone()));
         }
         if let Some(_size) = &self.by_size {
             size.replace(LimiterSize::new(*_size));
         }
-        let c = if let Some(compress) = &self.compress_exclude {
-            Compression::OnRotate(*compress)
-        } else {
-            Compression::None
+        let c = match &self.compress_exclude {
+            Some(compress) if self.codec != Codec::None => Compression::OnRotate(*compress),
+            _ => Compression::None,
         };
-        let backend;
+        let mut backend;
         if let Some(time_fmt) = self.time_fmt {
-            let file_limit = match self.upkeep {
-                Upkeep::Age(d) => FileLimit::Age(d),
-                Upkeep::Count(c) => FileLimit::MaxFiles(c),
-                Upkeep::All => FileLimit::Unlimited,
+            let tz = self.align.unwrap_or_default();
+            let schema = ArchiveTimestamp {
+                format: time_fmt,
+                last_time,
+                tz,
+                clock: clock.clone(),
+                archive_time: None,
             };
-            let schema = AppendTimestamp { format: time_fmt, file_limit, date_from };
-            backend = Backend::Time(UnsafeCell::new(_Backend::new(
+            let mut inner = _Backend::new(
                 archive_dir.clone(),
                 file_path,
                 self.upkeep,
                 c,
+                self.codec,
+                self.compress_idle_io,
                 schema,
-            )));
+                clock,
+                file_options,
+                lock_path,
+            )?;
+            if self.symlink {
+                let t = age.as_ref().map(|age| age.active_time()).unwrap_or_else(|| clock.now());
+                inner.init_symlink(t)?;
+            }
+            backend = Backend::Time(UnsafeCell::new(inner));
         } else {
-            let file_limit = match self.upkeep {
-                Upkeep::Age(_) => 0,
-                Upkeep::Count(c) => c,
-                Upkeep::All => 0,
-            };
-            let schema = AppendCount::new(file_limit);
+            // Upkeep is handled by _Backend
+            let schema = AppendCount::new(0);
             backend = Backend::Num(UnsafeCell::new(_Backend::new(
                 archive_dir.clone(),
                 file_path,
                 self.upkeep,
                 c,
+                self.codec,
+                self.compress_idle_io,
                 schema,
-            )));
+                clock,
+                file_options,
+                lock_path,
+            )?));
         }
-        return LogRotate {
+        backend.set_hooks(self.on_archive.clone(), self.on_delete.clone());
+        return Ok(LogRotate {
+            path: file_path.into(),
             size_limit: size,
             age_limit: age,
             backend: Arc::new(backend),
             th: Mutex::new(None),
-        };
+            on_start: AtomicBool::new(self.rotate_on_start),
+            failed: AtomicBool::new(false),
+            symlink: self.symlink,
+            file_options,
+        });
     }
 }
 
 pub(crate) struct LogRotate {
+    /// The active log file
+    path: Box<Path>,
     size_limit: Option<LimiterSize>,
     age_limit: Option<LimiterAge>,
     backend: Arc<Backend>,
     th: Mutex<Option<thread::JoinHandle<()>>>,
+    /// rotate_on_start is not checked yet
+    on_start: AtomicBool,
+    /// The last rotation failed and reported, until it succeeds again
+    failed: AtomicBool,
+    symlink: bool,
+    file_options: FileOptions,
 }
 
 impl LogRotate {
-    pub fn rotate<S: FileSinkTrait>(&self, sink: &S) -> bool {
-        let mut need_rotate = false;
+    pub fn need_rotate<S: FileSinkTrait>(&self, sink: &S) -> bool {
+        if self.on_start.load(Ordering::Relaxed)
+            && self.on_start.swap(false, Ordering::AcqRel)
+            && sink.get_size() > 0
+        {
+            return true;
+        }
         if let Some(age) = self.age_limit.as_ref() {
             if age.check(sink) {
-                need_rotate = true;
+                return true;
             }
         }
         if let Some(size) = self.size_limit.as_ref() {
             if size.check(sink) {
-                need_rotate = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    #[inline]
+    pub fn file_options(&self) -> &FileOptions {
+        &self.file_options
+    }
+
+    #[inline]
+    pub fn size_limit(&self) -> Option<u64> {
+        self.size_limit.as_ref().map(|s| s.limit)
+    }
+
+    /// The time to name the archive of the sink
+    #[inline]
+    fn archive_time<S: FileSinkTrait>(&self, sink: &S) -> Option<SystemTime> {
+        self.age_limit.as_ref().and_then(|age| age.archive_time(sink.get_create_time()))
+    }
+
+    /// Rename the file, or switch the symlink to a new file
+    #[inline]
+    fn archive<S: FileSinkTrait>(&self, sink: &S) -> io::Result<()> {
+        let r = if self.symlink {
+            match self.age_limit.as_ref() {
+                Some(age) => self.backend.switch_active(age.active_time()),
+                None => Err(invalid_input("symlink requires by_age")),
             }
+        } else {
+            self.backend.rename_files(self.archive_time(sink))
+        };
+        match &r {
+            Ok(_) => self.failed.store(false, Ordering::Release),
+            Err(e) => self.on_error(SinkErrorKind::Rotate, &self.path, e),
+        }
+        r
+    }
+
+    /// Report the error only on the first failure, since the rotation is retried on every write.
+    pub fn on_error(&self, kind: SinkErrorKind, path: &Path, e: &io::Error) {
+        if !self.failed.swap(true, Ordering::AcqRel) {
+            report_error(kind, &path.to_string_lossy(), e);
         }
-        if !need_rotate {
+    }
+
+    pub fn rotate<S: FileSinkTrait>(&self, sink: &S) -> bool {
+        if !self.need_rotate(sink) {
             return false;
         }
         self.wait();
 
-        self.backend.rename_files();
+        if self.archive(sink).is_err() {
+            return false;
+        }
         let backend = self.backend.clone();
         let th = thread::spawn(move || {
             let _ = backend.handle_old_files();
@@ -289,6 +660,50 @@ impl LogRotate {
         true
     }
 
+    /// Rotate for multiple processes writing to the same file.
+    ///
+    /// Should call wait() before holding the `lock_path`, and call this with the lock held.
+    /// Since other process might have rotated the files, rescan the archives before rename,
+    /// and the cleanup thread will hold the `lock_path` too.
+    ///
+    /// Other processes keep writing to the archived file until they notice the rename, so the
+    /// cleanup thread holds off the compression and upkeep for `delay`.
+    ///
+    /// Returns true when the file is archived.
+    pub fn rotate_shared<S: FileSinkTrait>(
+        &self, sink: &S, lock_path: &Path, delay: Duration,
+    ) -> bool {
+        self.backend.scan_suffixes();
+        if self.archive(sink).is_err() {
+            return false;
+        }
+        let backend = self.backend.clone();
+        let lock_path = lock_path.to_path_buf();
+        let file_options = self.file_options;
+        let th = thread::spawn(move || {
+            thread::sleep(delay);
+            match FileLock::lock(&lock_path, &file_options) {
+                Ok(_lock) => {
+                    backend.scan_suffixes();
+                    let _ = backend.handle_old_files();
+                }
+                Err(e) => {
+                    report_error(SinkErrorKind::Lock, &lock_path.to_string_lossy(), &e);
+                }
+            }
+        });
+        self.th.lock().replace(th);
+        true
+    }
+
+    /// Delete the oldest archive ahead of upkeep, returns false if there's none.
+    ///
+    /// Should be called with the lock of the sink held, like rotate().
+    pub fn purge_oldest(&self) -> bool {
+        self.wait();
+        self.backend.purge_oldest()
+    }
+
     /// Wait for the last handle_old_files to finish.
     pub fn wait(&self) {
         if let Some(th) = self.th.lock().take() {
@@ -313,53 +728,382 @@ impl LimiterSize {
 }
 
 pub(crate) struct LimiterAge {
-    limit: Duration,
+    age: Age,
+    align: Option<TimeZone>,
+    clock: Clock,
+    /// cache of (create_time, the time to rotate)
+    boundary: Mutex<Option<(SystemTime, SystemTime)>>,
 }
 
 impl LimiterAge {
-    pub fn new(limit: Age) -> Self {
-        Self {
-            limit: match limit {
-                Age::Hour => Duration::from_secs(60 * 60),
-                Age::Day => Duration::from_secs(24 * 60 * 60),
-            },
-        }
+    pub fn new(age: Age, align: Option<TimeZone>, clock: Clock) -> Self {
+        Self { age, align, clock, boundary: Mutex::new(None) }
     }
 
     pub fn check<S: FileSinkTrait>(&self, sink: &S) -> bool {
-        let now = SystemTime::now();
+        let now = self.clock.now();
         let start_ts = sink.get_create_time();
-        match now.duration_since(start_ts) {
-            Ok(d) => return d > self.limit,
-            Err(_) => return true, // system time rotate back
+        if now < start_ts {
+            return true; // system time rotate back
         }
+        let mut guard = self.boundary.lock();
+        let next = match *guard {
+            Some((create_time, next)) if create_time == start_ts => next,
+            _ => {
+                let next = if let Some(tz) = self.align {
+                    align_time(start_ts, self.age, tz, true)
+                } else {
+                    self.age.shift(start_ts, TimeZone::Local, true)
+                };
+                guard.replace((start_ts, next));
+                next
+            }
+        };
+        if self.align.is_some() {
+            now >= next
+        } else {
+            now > next
+        }
+    }
+
+    /// The time to name a new active file in symlink mode
+    #[inline]
+    pub fn active_time(&self) -> SystemTime {
+        let now = self.clock.now();
+        match self.align {
+            Some(tz) => align_time(now, self.age, tz, false),
+            None => now,
+        }
+    }
+
+    /// When aligned, the archive is named after the start of the period
+    #[inline]
+    pub fn archive_time(&self, create_time: SystemTime) -> Option<SystemTime> {
+        self.align.map(|tz| align_time(create_time, self.age, tz, false))
+    }
+}
+
+/// Return the start of the period (or the next period) which `t` is in.
+fn align_time(t: SystemTime, age: Age, tz: TimeZone, next: bool) -> SystemTime {
+    match tz {
+        TimeZone::Local => _align_time(&chrono::Local, t, age, next),
+        TimeZone::Utc => _align_time(&Utc, t, age, next),
+        TimeZone::Fixed(offset) => _align_time(&offset, t, age, next),
     }
 }
 
+fn _align_time<Tz: chrono::TimeZone>(tz: &Tz, t: SystemTime, age: Age, next: bool) -> SystemTime {
+    let dt = DateTime::<Utc>::from(t).with_timezone(tz);
+    let local = dt.naive_local();
+    let date = local.date();
+    let mut start: NaiveDateTime = match age {
+        Age::Minute => date.and_hms_opt(local.hour(), local.minute(), 0).unwrap(),
+        Age::Hour => date.and_hms_opt(local.hour(), 0, 0).unwrap(),
+        Age::Day => date.and_hms_opt(0, 0, 0).unwrap(),
+        Age::Week => {
+            let days = date.weekday().num_days_from_monday() as u64;
+            (date - chrono::Days::new(days)).and_hms_opt(0, 0, 0).unwrap()
+        }
+        Age::Month => date.with_day(1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+        Age::Every(d) => {
+            let secs = local.and_utc().timestamp();
+            let step = d.as_secs() as i64;
+            DateTime::from_timestamp(secs - secs.rem_euclid(step), 0).unwrap().naive_utc()
+        }
+    };
+    if next {
+        start = match age {
+            Age::Minute => start + chrono::TimeDelta::minutes(1),
+            Age::Hour => start + chrono::TimeDelta::hours(1),
+            Age::Day => start + chrono::Days::new(1),
+            Age::Week => start + chrono::Days::new(7),
+            Age::Month => start + chrono::Months::new(1),
+            Age::Every(d) => start + chrono::TimeDelta::seconds(d.as_secs() as i64),
+        };
+    }
+    from_local(tz, &dt, start)
+}
+
+/// Convert local time in tz back to SystemTime
+fn from_local<Tz: chrono::TimeZone>(
+    tz: &Tz, dt: &DateTime<Tz>, local: NaiveDateTime,
+) -> SystemTime {
+    match tz.from_local_datetime(&local).earliest() {
+        Some(d) => d.into(),
+        None => {
+            // In the gap of daylight saving time, use the offset of `dt`.
+            let offset = dt.offset().fix().local_minus_utc() as i64;
+            Utc.from_utc_datetime(&(local - chrono::TimeDelta::seconds(offset))).into()
+        }
+    }
+}
+
+fn shift_month<Tz: chrono::TimeZone>(tz: &Tz, t: SystemTime, forward: bool) -> SystemTime {
+    let dt = DateTime::<Utc>::from(t).with_timezone(tz);
+    let local = dt.naive_local();
+    let shifted = if forward {
+        local.checked_add_months(chrono::Months::new(1))
+    } else {
+        local.checked_sub_months(chrono::Months::new(1))
+    };
+    from_local(tz, &dt, shifted.unwrap_or(local))
+}
+
+#[inline]
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
 pub(crate) trait FileSinkTrait {
     fn get_create_time(&self) -> SystemTime;
 
     fn get_size(&self) -> u64;
 }
 
+/// Exclusive flock on a lock file, to coordinate rotation among processes.
+/// Unlocked on drop.
+pub(crate) struct FileLock {
+    _f: File,
+}
+
+impl FileLock {
+    pub fn lock(path: &Path, file_options: &FileOptions) -> io::Result<Self> {
+        let f = file_options.open(path)?;
+        flock(&f, libc::LOCK_EX)?;
+        Ok(Self { _f: f })
+    }
+}
+
+#[inline]
+fn flock(f: &File, op: libc::c_int) -> io::Result<()> {
+    loop {
+        if unsafe { libc::flock(f.as_raw_fd(), op) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Whether the file is locked by a compressor (of this or other process) in progress.
+fn is_compressing(path: &Path) -> bool {
+    match File::open(path) {
+        Ok(f) => flock(&f, libc::LOCK_EX | libc::LOCK_NB).is_err(),
+        Err(_) => false,
+    }
+}
+
+/// Same naming as AppendTimestamp of file-rotate, which can not be used here because:
+///
+/// - It always takes the time from `Local::now()`, ignoring [Builder::clock()](crate::Builder::clock())
+///   and the [TimeZone] of [Rotation::align()], for both the archive names and `FileLimit::Age`.
+/// - Its `DateFrom` only shifts back by a day or an hour, not the other [Age].
+/// - The name can not be set to the start of the aligned period, or the period of the file
+///   being rotated on startup, so we take it from `archive_time` when specified.
+struct ArchiveTimestamp {
+    format: &'static str,
+    /// Name the archive with one period ago
+    last_time: Option<Age>,
+    tz: TimeZone,
+    clock: Clock,
+    archive_time: Option<SystemTime>,
+}
+
+impl SuffixScheme for ArchiveTimestamp {
+    type Repr = TimestampSuffix;
+
+    fn rotate_file(
+        &mut self, _basepath: &Path, newest_suffix: Option<&TimestampSuffix>,
+        suffix: &Option<TimestampSuffix>,
+    ) -> io::Result<TimestampSuffix> {
+        if suffix.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Critical error in file-rotate algorithm",
+            ));
+        }
+        let t = match self.archive_time.take() {
+            Some(t) => t,
+            None => {
+                let now = self.clock.now();
+                match self.last_time {
+                    Some(age) => age.shift(now, self.tz, false),
+                    None => now,
+                }
+            }
+        };
+        let timestamp = self.tz.format(&t.into(), self.format);
+        let number = match newest_suffix {
+            Some(newest) if newest.timestamp == timestamp => Some(newest.number.unwrap_or(0) + 1),
+            _ => None,
+        };
+        Ok(TimestampSuffix { timestamp, number })
+    }
+
+    fn parse(&self, suffix: &str) -> Option<Self::Repr> {
+        let (timestamp_str, n) = if let Some(dot) = suffix.find('.') {
+            if let Ok(n) = suffix[(dot + 1)..].parse::<usize>() {
+                (&suffix[..dot], Some(n))
+            } else {
+                return None;
+            }
+        } else {
+            (suffix, None)
+        };
+        let success = match NaiveDateTime::parse_from_str(timestamp_str, self.format) {
+            Ok(_) => true,
+            Err(e) => e.kind() == chrono::format::ParseErrorKind::NotEnough,
+        };
+        if success {
+            Some(TimestampSuffix { timestamp: timestamp_str.to_string(), number: n })
+        } else {
+            None
+        }
+    }
+
+    /// Upkeep is handled by _Backend
+    fn too_old(&self, _suffix: &TimestampSuffix, _file_number: usize) -> bool {
+        false
+    }
+}
+
+/// Compression algorithm for the archives
+#[derive(Hash, Clone, Copy, PartialEq, Debug)]
+pub enum Codec {
+    /// Do not compress, even when [Rotation::compress_exclude] is set.
+    None,
+    /// gzip with level 0-9, archived as `.gz`
+    Gzip(u32),
+    /// zstd with level 1-22, archived as `.zst`
+    #[cfg(feature = "zstd")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "zstd")))]
+    Zstd(i32),
+}
+
+impl Default for Codec {
+    #[inline]
+    fn default() -> Self {
+        Self::Gzip(6)
+    }
+}
+
+/// Extensions of the compressed archives, recognized regardless of codec configured.
+const CODEC_EXTS: [&str; 2] = [".gz", ".zst"];
+
+/// Extension of the file during compression
+const TMP_EXT: &str = ".tmp";
+
+impl Codec {
+    #[inline]
+    fn ext(&self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Gzip(_) => ".gz",
+            #[cfg(feature = "zstd")]
+            Self::Zstd(_) => ".zst",
+        }
+    }
+}
+
+/// Tell the age of archives for Upkeep
+trait ArchiveScheme: SuffixScheme {
+    /// Return None if the scheme does not know the time of the archive.
+    fn is_expired(
+        &self, suffix: &Self::Repr, now: SystemTime, max_age: chrono::TimeDelta,
+    ) -> Option<bool>;
+}
+
+impl ArchiveScheme for AppendCount {
+    #[inline]
+    fn is_expired(
+        &self, _suffix: &usize, _now: SystemTime, _max_age: chrono::TimeDelta,
+    ) -> Option<bool> {
+        None
+    }
+}
+
+impl ArchiveScheme for ArchiveTimestamp {
+    #[inline]
+    fn is_expired(
+        &self, suffix: &TimestampSuffix, now: SystemTime, max_age: chrono::TimeDelta,
+    ) -> Option<bool> {
+        let old: DateTime<Utc> = DateTime::<Utc>::from(now) - max_age;
+        Some(suffix.timestamp < self.tz.format(&old, self.format))
+    }
+}
+
 enum Backend {
     Num(UnsafeCell<_Backend<AppendCount>>),
-    Time(UnsafeCell<_Backend<AppendTimestamp>>),
+    Time(UnsafeCell<_Backend<ArchiveTimestamp>>),
 }
 
 unsafe impl Send for Backend {}
 unsafe impl Sync for Backend {}
 
 impl Backend {
-    fn rename_files(&self) {
+    fn set_hooks(&mut self, on_archive: Option<ArchiveHook>, on_delete: Option<ArchiveHook>) {
+        match self {
+            Self::Num(inner) => {
+                let inner = inner.get_mut();
+                inner.on_archive = on_archive;
+                inner.on_delete = on_delete;
+            }
+            Self::Time(inner) => {
+                let inner = inner.get_mut();
+                inner.on_archive = on_archive;
+                inner.on_delete = on_delete;
+            }
+        }
+    }
+
+    fn scan_suffixes(&self) {
+        match self {
+            Self::Num(_inner) => {
+                let inner: &mut _Backend<AppendCount> = unsafe { transmute(_inner.get()) };
+                inner.scan_suffixes();
+            }
+            Self::Time(_inner) => {
+                let inner: &mut _Backend<ArchiveTimestamp> = unsafe { transmute(_inner.get()) };
+                inner.scan_suffixes();
+            }
+        }
+    }
+
+    fn rename_files(&self, archive_time: Option<SystemTime>) -> io::Result<()> {
+        match self {
+            Self::Num(_inner) => {
+                let inner: &mut _Backend<AppendCount> = unsafe { transmute(_inner.get()) };
+                inner.rename_files()
+            }
+            Self::Time(_inner) => {
+                let inner: &mut _Backend<ArchiveTimestamp> = unsafe { transmute(_inner.get()) };
+                inner.suffix_scheme.archive_time = archive_time;
+                inner.rename_files()
+            }
+        }
+    }
+
+    fn switch_active(&self, t: SystemTime) -> io::Result<()> {
+        match self {
+            Self::Num(_) => Err(invalid_input("symlink requires time_fmt")),
+            Self::Time(_inner) => {
+                let inner: &mut _Backend<ArchiveTimestamp> = unsafe { transmute(_inner.get()) };
+                inner.switch_active(t)
+            }
+        }
+    }
+
+    fn purge_oldest(&self) -> bool {
         match self {
             Self::Num(_inner) => {
                 let inner: &mut _Backend<AppendCount> = unsafe { transmute(_inner.get()) };
-                inner.rename_files();
+                inner.purge_oldest()
             }
             Self::Time(_inner) => {
-                let inner: &mut _Backend<AppendTimestamp> = unsafe { transmute(_inner.get()) };
-                inner.rename_files();
+                let inner: &mut _Backend<ArchiveTimestamp> = unsafe { transmute(_inner.get()) };
+                inner.purge_oldest()
             }
         }
     }
@@ -371,126 +1115,471 @@ impl Backend {
                 inner.handle_old_files()
             }
             Self::Time(_inner) => {
-                let inner: &mut _Backend<AppendTimestamp> = unsafe { transmute(_inner.get()) };
+                let inner: &mut _Backend<ArchiveTimestamp> = unsafe { transmute(_inner.get()) };
                 inner.handle_old_files()
             }
         }
     }
 }
 
+/// An archived file, ordered by the suffix.
+#[derive(Clone, Debug)]
+struct ArchiveInfo<Repr> {
+    suffix: Repr,
+    /// Extension of the codec, empty when not compressed
+    ext: &'static str,
+}
+
+impl<R: PartialEq> PartialEq for ArchiveInfo<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.suffix == other.suffix
+    }
+}
+
+impl<R: Eq> Eq for ArchiveInfo<R> {}
+
+impl<R: Ord> PartialOrd for ArchiveInfo<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R: Ord> Ord for ArchiveInfo<R> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.suffix.cmp(&other.suffix)
+    }
+}
+
+impl<Repr: Representation> ArchiveInfo<Repr> {
+    #[inline]
+    fn to_path(&self, basepath: &Path) -> PathBuf {
+        let path = self.suffix.to_path(basepath);
+        if self.ext.is_empty() {
+            path
+        } else {
+            PathBuf::from(format!("{}{}", path.display(), self.ext))
+        }
+    }
+
+    #[inline]
+    fn compressed(&self) -> bool {
+        !self.ext.is_empty()
+    }
+}
+
+/// Return the file name without the codec extension, and the extension
+#[inline]
+fn strip_codec_ext(name: &str) -> (&str, &'static str) {
+    for ext in CODEC_EXTS {
+        if let Some(stripped) = name.strip_suffix(ext) {
+            return (stripped, ext);
+        }
+    }
+    (name, "")
+}
+
 /// Adaptation to file-rotate crate (Copyright (c) 2020 BourgondAries, MIT license)
-struct _Backend<S: SuffixScheme> {
+struct _Backend<S: ArchiveScheme> {
     archive_dir: PathBuf,
     base_path: PathBuf, // log_path replaced parent with archive_dir
     log_path: PathBuf,  // current log
     compress: Compression,
+    codec: Codec,
+    idle_io: bool,
+    file_options: FileOptions,
     suffix_scheme: S,
-    /// The bool is whether or not there's a .gz suffix to the filename
-    suffixes: BTreeSet<SuffixInfo<S::Repr>>,
+    suffixes: BTreeSet<ArchiveInfo<S::Repr>>,
     upkeep: Upkeep,
+    clock: Clock,
+    on_archive: Option<ArchiveHook>,
+    on_delete: Option<ArchiveHook>,
+    /// The archive created by last rename_files, not notified yet
+    new_archive: Option<ArchiveInfo<S::Repr>>,
+}
+
+/// Compress into a temporary file, rename to the archive after done, and then remove the source.
+/// So that the crash during compression will not leave an incomplete archive.
+///
+/// The temporary file (and the archive after rename) is flocked until the source is removed,
+/// so that [_Backend::recover()] of other sinks leave it alone.
+fn compress(path: &Path, codec: Codec, file_options: &FileOptions) -> io::Result<()> {
+    if codec == Codec::None {
+        // Compression::OnRotate is never built with Codec::None, nothing to compress
+        return Ok(());
+    }
+    let dest_path = PathBuf::from(format!("{}{}", path.display(), codec.ext()));
+    let tmp_path = PathBuf::from(format!("{}{}", dest_path.display(), TMP_EXT));
+
+    let mut lock = None;
+    let mut _compress = || -> io::Result<()> {
+        let mut src_file = File::open(path)?;
+        let mut oo = OpenOptions::new();
+        oo.write(true).create(true).truncate(true);
+        if let Some(mode) = file_options.mode {
+            oo.mode(mode);
+        }
+        let dest_file = oo.open(&tmp_path)?;
+        let _lock = dest_file.try_clone()?;
+        flock(&_lock, libc::LOCK_EX)?;
+        lock = Some(_lock);
+        file_options.apply(&dest_file)?;
+        let dest_file = match codec {
+            Codec::None => unreachable!(),
+            Codec::Gzip(level) => {
+                let mut encoder = GzEncoder::new(dest_file, flate2::Compression::new(level));
+                io::copy(&mut src_file, &mut encoder)?;
+                encoder.finish()?
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => {
+                let mut encoder = zstd::Encoder::new(dest_file, level)?;
+                io::copy(&mut src_file, &mut encoder)?;
+                encoder.finish()?
+            }
+        };
+        dest_file.sync_all()?;
+        fs::rename(&tmp_path, &dest_path)
+    };
+    if let Err(e) = _compress() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    fs::remove_file(path)?;
+    drop(lock);
+    Ok(())
 }
 
-fn compress(path: &Path) -> io::Result<()> {
-    let dest_path = PathBuf::from(format!("{}.gz", path.display()));
+/// Set the I/O priority of current thread to idle class
+#[inline]
+fn set_idle_io_priority() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        );
+    }
+}
 
-    let mut src_file = File::open(path)?;
-    let dest_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .append(false)
-        .open(&dest_path)?;
+impl _Backend<ArchiveTimestamp> {
+    /// Create a new time-stamped file `<file>.<timestamp>[.<n>]` in the dir of log, and return
+    /// the suffix. When `from` is Some, move it to the new name instead of creating an empty file.
+    fn new_active(&self, t: SystemTime, from: Option<&Path>) -> io::Result<TimestampSuffix> {
+        let scheme = &self.suffix_scheme;
+        let timestamp = scheme.tz.format(&t.into(), scheme.format);
+        let mut info = ArchiveInfo { suffix: TimestampSuffix { timestamp, number: None }, ext: "" };
+        loop {
+            let path = info.suffix.to_path(&self.log_path);
+            if !path.exists() && !self.suffixes.contains(&info) {
+                if let Some(from) = from {
+                    fs::rename(from, &path)?;
+                    self.file_options.apply_path(&path)?;
+                } else {
+                    self.file_options.open(&path)?;
+                }
+                return Ok(info.suffix);
+            }
+            info.suffix.number = Some(info.suffix.number.unwrap_or(0) + 1);
+        }
+    }
 
-    assert!(path.exists());
-    assert!(dest_path.exists());
-    let mut encoder = GzEncoder::new(dest_file, flate2::Compression::default());
-    io::copy(&mut src_file, &mut encoder)?;
+    /// Point `<file>` to the suffix, by replacing the symlink atomically
+    fn link_active(&self, suffix: &TimestampSuffix) -> io::Result<()> {
+        let target = suffix.to_path(Path::new(self.log_path.file_name().unwrap()));
+        let tmp_path = PathBuf::from(format!("{}.link{}", self.log_path.display(), TMP_EXT));
+        let _ = fs::remove_file(&tmp_path);
+        std::os::unix::fs::symlink(target, &tmp_path)?;
+        fs::rename(&tmp_path, &self.log_path)
+    }
 
-    fs::remove_file(path)?;
+    /// Ensure `<file>` is a symlink on startup
+    fn init_symlink(&mut self, t: SystemTime) -> io::Result<()> {
+        match fs::symlink_metadata(&self.log_path) {
+            Ok(mt) if mt.file_type().is_symlink() => {}
+            Ok(_) => {
+                // Regular file written without symlink
+                let suffix = self.new_active(t, Some(&self.log_path))?;
+                self.link_active(&suffix)?;
+            }
+            Err(_) => {
+                let suffix = self.new_active(t, None)?;
+                self.link_active(&suffix)?;
+            }
+        }
+        self.scan_suffixes();
+        Ok(())
+    }
 
-    Ok(())
+    /// Switch `<file>` to a new time-stamped file, the old one becomes the archive.
+    fn switch_active(&mut self, t: SystemTime) -> io::Result<()> {
+        self.ensure_dir()?;
+        let old = self.active_name();
+        let suffix = self.new_active(t, None)?;
+        self.link_active(&suffix)?;
+        if let Some(old) = old {
+            if let Some(old_suffix) = self.suffix_scheme.parse(&old) {
+                let old_path = old_suffix.to_path(&self.log_path);
+                let archive_path = old_suffix.to_path(&self.base_path);
+                if old_path != archive_path {
+                    fs::rename(&old_path, &archive_path)?;
+                }
+                if let Err(e) = self.file_options.apply_path(&archive_path) {
+                    report_error(SinkErrorKind::Rotate, &archive_path.to_string_lossy(), &e);
+                }
+                let info = ArchiveInfo { suffix: old_suffix, ext: "" };
+                self.new_archive.replace(info.clone());
+                self.suffixes.insert(info);
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<S: SuffixScheme> _Backend<S> {
+impl<S: ArchiveScheme> _Backend<S> {
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        archive_dir: PathBuf, file: &Path, upkeep: Upkeep, compress: Compression, schema: S,
-    ) -> Self {
-        let base_path = archive_dir.as_path().join(Path::new(file.file_name().unwrap()));
+        archive_dir: PathBuf, file: &Path, upkeep: Upkeep, compress: Compression, codec: Codec,
+        idle_io: bool, schema: S, clock: &Clock, file_options: FileOptions,
+        lock_path: Option<&Path>,
+    ) -> io::Result<Self> {
+        let file_name = file.file_name().ok_or_else(|| invalid_input("invalid log file name"))?;
+        let base_path = archive_dir.as_path().join(Path::new(file_name));
         let mut s = Self {
             archive_dir,
             log_path: file.to_path_buf(),
             base_path,
             upkeep,
             compress,
+            codec,
+            idle_io,
+            file_options,
             suffix_scheme: schema,
             suffixes: BTreeSet::new(),
+            clock: clock.clone(),
+            on_archive: None,
+            on_delete: None,
+            new_archive: None,
         };
-        s.ensure_dir();
+        s.ensure_dir()?;
+        {
+            // Other processes might be rotating, the lock file is created on the first rotation
+            let _lock = match lock_path {
+                Some(path) if path.exists() => Some(FileLock::lock(path, &file_options)?),
+                _ => None,
+            };
+            s.recover();
+        }
         s.scan_suffixes();
-        s
+        Ok(s)
     }
 
-    #[inline]
-    fn ensure_dir(&self) {
-        if !self.archive_dir.exists() {
-            fs::create_dir_all(&self.archive_dir).expect("create dir");
+    /// List the file names in archive_dir (without the prefix `<base name>.`)
+    fn list_archives(&self) -> Vec<String> {
+        let prefix = format!("{}.", self.base_path.file_name().unwrap().to_string_lossy());
+        let mut names = Vec::new();
+        if let Ok(dir) = fs::read_dir(&self.archive_dir) {
+            for entry in dir.filter_map(|entry| entry.ok()) {
+                if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                    continue;
+                }
+                if let Some(name) = entry.file_name().to_string_lossy().strip_prefix(&prefix) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names
+    }
+
+    /// Cleanup after crash during compression: remove the temporary files, and the compressed
+    /// archives which uncompressed source still exists (may be incomplete).
+    ///
+    /// Skip the files still locked by a compressor, which might be the cleanup thread of the
+    /// previous setup, or other processes.
+    fn recover(&self) {
+        let archive_path = |name: &str| {
+            let mut path = self.base_path.clone().into_os_string();
+            path.push(".");
+            path.push(name);
+            PathBuf::from(path)
+        };
+        for name in self.list_archives() {
+            let path = archive_path(&name);
+            if name.ends_with(TMP_EXT) {
+                if !is_compressing(&path) {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            }
+            let (stripped, ext) = strip_codec_ext(&name);
+            if !ext.is_empty()
+                && self.suffix_scheme.parse(stripped).is_some()
+                && archive_path(stripped).exists()
+                && !is_compressing(&path)
+            {
+                let _ = fs::remove_file(&path);
+            }
         }
     }
 
     #[inline]
+    fn ensure_dir(&self) -> io::Result<()> {
+        self.file_options.create_dir(&self.archive_dir)
+    }
+
+    /// The name of the active file (without the prefix `<base name>.`) in symlink mode
+    fn active_name(&self) -> Option<String> {
+        let target = fs::read_link(&self.log_path).ok()?;
+        let prefix = format!("{}.", self.log_path.file_name().unwrap().to_string_lossy());
+        target.file_name()?.to_string_lossy().strip_prefix(&prefix).map(|name| name.to_string())
+    }
+
     fn scan_suffixes(&mut self) {
-        self.suffixes = self.suffix_scheme.scan_suffixes(&self.base_path);
+        let mut suffixes = BTreeSet::new();
+        let active = self.active_name();
+        for name in self.list_archives() {
+            if name.ends_with(TMP_EXT) || Some(&name) == active.as_ref() {
+                continue;
+            }
+            let (stripped, ext) = strip_codec_ext(&name);
+            if let Some(suffix) = self.suffix_scheme.parse(stripped) {
+                suffixes.insert(ArchiveInfo { suffix, ext });
+            }
+        }
+        self.suffixes = suffixes;
     }
 
     #[inline]
-    fn rename_files(&mut self) {
-        self.ensure_dir();
-        let new_suffix_info = self._move_file_with_suffix(None).expect("move files");
+    fn rename_files(&mut self) -> io::Result<()> {
+        self.ensure_dir()?;
+        let new_suffix_info = self._move_file_with_suffix(None)?;
         self.suffixes.insert(new_suffix_info);
+        // The returned suffix is the last one in the cascade of renames, the newest is the first.
+        self.new_archive = self.suffixes.iter().next().cloned();
+        if let Some(info) = self.new_archive.as_ref() {
+            // The file is already rotated, do not fail
+            let path = info.to_path(&self.base_path);
+            if let Err(e) = self.file_options.apply_path(&path) {
+                report_error(SinkErrorKind::Rotate, &path.to_string_lossy(), &e);
+            }
+        }
+        Ok(())
+    }
+
+    fn purge_oldest(&mut self) -> bool {
+        // Other process might have rotated or removed the files
+        self.scan_suffixes();
+        if let Some(suffix) = self.suffixes.pop_last() {
+            let path = suffix.to_path(&self.base_path);
+            if let Some(hook) = self.on_delete.as_ref() {
+                hook.call(&path);
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                report_error(SinkErrorKind::Rotate, &path.to_string_lossy(), &e);
+            }
+            return true;
+        }
+        false
     }
 
     #[inline]
     fn handle_old_files(&mut self) -> io::Result<()> {
-        // Find the youngest suffix that is too old, and then remove all suffixes that are older or
-        // equally old:
-        // Start from oldest suffix, stop when we find a suffix that is not too old
+        // Find the youngest suffix that exceeds any of the limits, and then remove all suffixes
+        // that are older or equally old:
+        // Start from youngest suffix, accumulate the total size.
         let mut result = Ok(());
-        if let Upkeep::All = &self.upkeep {
-        } else {
+        let new_archive = self.new_archive.take();
+        let (max_age, max_count, max_size) = self.upkeep.limits();
+        if max_age.is_some() || max_count.is_some() || max_size.is_some() {
+            let now = self.clock.now();
+            let mut total_size = 0;
             let mut youngest_old = None;
-            for (i, suffix) in self.suffixes.iter().enumerate().rev() {
-                if self.suffix_scheme.too_old(&suffix.suffix, i) {
-                    result = result.and(fs::remove_file(suffix.to_path(&self.base_path)));
-                    youngest_old = Some((*suffix).clone());
-                } else {
-                    break;
+            for (i, suffix) in self.suffixes.iter().enumerate() {
+                let path = suffix.to_path(&self.base_path);
+                let mt = fs::metadata(&path).ok();
+                if let Some(max_count) = max_count {
+                    if i >= max_count {
+                        youngest_old = Some(suffix.clone());
+                        break;
+                    }
+                }
+                if let Some(max_age) = max_age {
+                    let expired = match self.suffix_scheme.is_expired(&suffix.suffix, now, max_age)
+                    {
+                        Some(expired) => expired,
+                        None => match mt.as_ref().and_then(|mt| mt.modified().ok()) {
+                            Some(mtime) => match (now.duration_since(mtime), max_age.to_std()) {
+                                (Ok(age), Ok(max_age)) => age > max_age,
+                                _ => false,
+                            },
+                            None => false,
+                        },
+                    };
+                    if expired {
+                        youngest_old = Some(suffix.clone());
+                        break;
+                    }
+                }
+                if let Some(max_size) = max_size {
+                    total_size += mt.as_ref().map(|mt| mt.len()).unwrap_or(0);
+                    if total_size > max_size {
+                        youngest_old = Some(suffix.clone());
+                        break;
+                    }
                 }
             }
             if let Some(youngest_old) = youngest_old {
                 // Removes all the too old
-                let _ = self.suffixes.split_off(&youngest_old);
+                for suffix in self.suffixes.split_off(&youngest_old) {
+                    let path = suffix.to_path(&self.base_path);
+                    if let Some(hook) = self.on_delete.as_ref() {
+                        hook.call(&path);
+                    }
+                    result = result.and(fs::remove_file(&path));
+                }
             }
         }
 
         // Compression
+        let mut suffixes_to_compress = Vec::new();
         if let Compression::OnRotate(max_file_n) = self.compress {
             let n = (self.suffixes.len() as i32 - max_file_n as i32).max(0) as usize;
             // The oldest N files should be compressed
-            let suffixes_to_compress = self
+            suffixes_to_compress = self
                 .suffixes
                 .iter()
                 .rev()
                 .take(n)
-                .filter(|info| !info.compressed)
+                .filter(|info| !info.compressed())
                 .cloned()
                 .collect::<Vec<_>>();
-            for info in suffixes_to_compress {
-                // Do the compression
-                let path = info.suffix.to_path(&self.base_path);
-                compress(&path)?;
+        }
+        if let (Some(hook), Some(new_archive)) = (self.on_archive.as_ref(), new_archive) {
+            // Might be deleted by upkeep already, or notified after compression below
+            if let Some(info) = self.suffixes.get(&new_archive) {
+                if !suffixes_to_compress.contains(info) {
+                    hook.call(&info.to_path(&self.base_path));
+                }
+            }
+        }
+        if self.idle_io && !suffixes_to_compress.is_empty() {
+            // Only affects current thread, which is spawned for each rotation
+            set_idle_io_priority();
+        }
+        for info in suffixes_to_compress {
+            // Do the compression
+            let path = info.suffix.to_path(&self.base_path);
+            compress(&path, self.codec, &self.file_options)?;
 
-                self.suffixes.replace(SuffixInfo { compressed: true, ..info });
+            let info = ArchiveInfo { ext: self.codec.ext(), ..info };
+            if let Some(hook) = self.on_archive.as_ref() {
+                hook.call(&info.to_path(&self.base_path));
             }
+            self.suffixes.replace(info);
         }
         result
     }
@@ -501,11 +1590,11 @@ impl<S: SuffixScheme> _Backend<S> {
     ///
     /// Returns the suffix of the new file (the last suffix after possible cascade of renames).
     fn _move_file_with_suffix(
-        &mut self, old_suffix_info: Option<SuffixInfo<S::Repr>>,
-    ) -> io::Result<SuffixInfo<S::Repr>> {
+        &mut self, old_suffix_info: Option<ArchiveInfo<S::Repr>>,
+    ) -> io::Result<ArchiveInfo<S::Repr>> {
         // NOTE: this newest_suffix is there only because AppendTimestamp specifically needs
         // it. Otherwise it might not be necessary to provide this to `rotate_file`. We could also
-        // have passed the internal BTreeMap itself, but it would require to make SuffixInfo `pub`.
+        // have passed the internal BTreeMap itself.
         let newest_suffix = self.suffixes.iter().next().map(|info| &info.suffix);
 
         let new_suffix = self.suffix_scheme.rotate_file(
@@ -515,9 +1604,9 @@ impl<S: SuffixScheme> _Backend<S> {
         )?;
 
         // The destination file/path eventual .gz suffix must match the source path
-        let new_suffix_info = SuffixInfo {
+        let new_suffix_info = ArchiveInfo {
             suffix: new_suffix,
-            compressed: old_suffix_info.as_ref().map(|x| x.compressed).unwrap_or(false),
+            ext: old_suffix_info.as_ref().map(|x| x.ext).unwrap_or(""),
         };
         let new_path = new_suffix_info.to_path(&self.base_path);
 
@@ -541,8 +1630,18 @@ impl<S: SuffixScheme> _Backend<S> {
             None => self.log_path.clone(), // When archive_dir and parent of log_path is different
         };
         // Do the move
-        assert!(old_path.exists());
-        assert!(!new_path.exists());
+        if !old_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", old_path.display()),
+            ));
+        }
+        if new_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", new_path.display()),
+            ));
+        }
         fs::rename(old_path, new_path)?;
 
         Ok(newly_created_suffix)
@@ -0,0 +1,379 @@
+//! # Per-test log isolation
+//!
+//! By default, all the test cases share one global logger, so when tests run in parallel, logs
+//! from different test cases are mixed into one file. That's why we used to recommend
+//! `--test-threads=1`.
+//!
+//! The attribute macro [#\[logfn\]](crate::logfn) enters a [LogScope] on the outer most function
+//! it wraps, and every record logged inside (including the panic message) is tagged with the scope name.
+//! The scope name is the current thread name (the test harness name the thread after the test
+//! case, for example `tests::test_foo` or `test_rstest_foo::case_1`), or fallback to the function name.
+//! For async function, the scope is carried by the future, so it works with `#[tokio::test]`.
+//!
+//! The scope is only entered under `cfg(test)` (unit tests and integration tests), so `#[logfn]`
+//! in production code costs nothing more than the enter / return logs.
+//!
+//! The scope name is available in the format by [FormatRecord::scope()](crate::FormatRecord::scope()).
+//!
+//! Combine with the [LogPerTest] sink:
+//!
+//! * [LogPerTest::dir()]: write one log file per test case, as `<dir>/<scope name>.log`
+//!
+//! * [LogPerTest::on_failure()]: keep the logs in memory, and only print the logs of a test case
+//!   to console when it fails (panic). Or append them to a file by [LogPerTest::on_failure_file()].
+//!
+//! ``` rust
+//! use captains_log::{*, scope::LogPerTest};
+//!
+//! #[logfn]
+//! #[test]
+//! fn test_foo() {
+//!     let builder = recipe::per_test_file_logger("/tmp/test_logs", Level::Debug);
+//!     builder.build().expect("setup log");
+//!     info!("will be written to /tmp/test_logs/test_foo.log");
+//! }
+//! ```
+//!
+//! Records logged outside any scope (for example, from a thread spawned by the test) are written
+//! to `<dir>/unscoped.log` by `LogPerTest::dir()`, and are ignored by `LogPerTest::on_failure()`.
+
+use crate::{
+    config::{Builder, LogFormat, SinkConfigBuild, SinkConfigTrait},
+    console_impl::ConsoleTarget,
+    file_impl::open_file,
+    formatter::RecordContext,
+    log_impl::{get_global_logger, LogSink, LogSinkTrait},
+    report::{report_error, SinkErrorKind},
+    time::Timer,
+};
+use log::{Level, Record};
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::task::{Context, Poll};
+use std::thread;
+
+/// The file name (without ".log") for the records outside any scope.
+pub const UNSCOPED_NAME: &str = "unscoped";
+
+thread_local! {
+    static CURRENT: RefCell<Option<LogScope>> = const { RefCell::new(None) };
+}
+
+struct ScopeInner {
+    name: Arc<str>,
+    failed: AtomicBool,
+}
+
+/// A scope of logs, usually represents a test case. See [module level doc](crate::scope).
+#[derive(Clone)]
+pub struct LogScope(Arc<ScopeInner>);
+
+impl LogScope {
+    fn new(fn_name: &str) -> Self {
+        let name: Arc<str> = match thread::current().name() {
+            Some(name) if name != "main" => name.into(),
+            _ => fn_name.into(),
+        };
+        Self(Arc::new(ScopeInner { name, failed: AtomicBool::new(false) }))
+    }
+
+    /// Enter a scope on current thread, do nothing if current thread is already in a scope.
+    ///
+    /// The scope ends when the returned guard drops.
+    pub fn enter(fn_name: &str) -> LogScopeGuard {
+        if current_scope().is_some() {
+            return LogScopeGuard(None);
+        }
+        let scope = Self::new(fn_name);
+        CURRENT.with(|c| c.borrow_mut().replace(scope.clone()));
+        LogScopeGuard(Some(scope))
+    }
+
+    /// Wrap a future, so that the scope is set when it's polled, regardless of which thread.
+    /// Do nothing if current thread is already in a scope.
+    ///
+    /// The scope ends when the future drops.
+    pub fn instrument<F: Future>(fn_name: &str, f: F) -> Scoped<F> {
+        let scope = if current_scope().is_some() { None } else { Some(Self::new(fn_name)) };
+        Scoped { scope, inner: f }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &Arc<str> {
+        &self.0.name
+    }
+
+    #[inline]
+    pub fn is_failed(&self) -> bool {
+        self.0.failed.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    fn set_failed(&self) {
+        self.0.failed.store(true, Ordering::Release);
+    }
+
+    fn finish(&self) {
+        if thread::panicking() {
+            self.set_failed();
+        }
+        if let Some(logger) = get_global_logger() {
+            logger.scope_end(self.name(), self.is_failed());
+        }
+    }
+}
+
+/// Return the name of the scope current thread (or task) is in.
+#[inline]
+pub fn current_scope() -> Option<Arc<str>> {
+    CURRENT.with(|c| c.borrow().as_ref().map(|s| s.name().clone()))
+}
+
+/// Called from panic hook, mark the current scope as failed.
+#[inline]
+pub(crate) fn mark_failed() {
+    CURRENT.with(|c| {
+        if let Some(s) = c.borrow().as_ref() {
+            s.set_failed();
+        }
+    });
+}
+
+/// Returned by [LogScope::enter()], the scope ends when dropped.
+pub struct LogScopeGuard(Option<LogScope>);
+
+impl Drop for LogScopeGuard {
+    fn drop(&mut self) {
+        if let Some(scope) = self.0.take() {
+            CURRENT.with(|c| c.borrow_mut().take());
+            scope.finish();
+        }
+    }
+}
+
+/// A future wrapper returned by [LogScope::instrument()]
+pub struct Scoped<F: Future> {
+    scope: Option<LogScope>,
+    inner: F,
+}
+
+/// Restore the scope of the thread after polling, and mark failure on panic.
+struct PollGuard<'a>(&'a LogScope);
+
+impl Drop for PollGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.set_failed();
+        }
+        CURRENT.with(|c| c.borrow_mut().take());
+    }
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: inner is never moved out
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        if let Some(scope) = this.scope.as_ref() {
+            if current_scope().is_none() {
+                CURRENT.with(|c| c.borrow_mut().replace(scope.clone()));
+                let _guard = PollGuard(scope);
+                return inner.poll(cx);
+            }
+        }
+        inner.poll(cx)
+    }
+}
+
+impl<F: Future> Drop for Scoped<F> {
+    fn drop(&mut self) {
+        if let Some(scope) = self.scope.take() {
+            scope.finish();
+        }
+    }
+}
+
+/// Where [LogPerTest] writes to
+#[derive(Hash)]
+pub enum PerTestOutput {
+    /// One file per scope as `<dir>/<scope name>.log`
+    Dir(Box<Path>),
+    /// Keep the logs in memory, print to console when the scope fails.
+    OnFailure(ConsoleTarget),
+    /// Keep the logs in memory, append to the file when the scope fails.
+    OnFailureFile(Box<Path>),
+}
+
+/// Config for per-test log sink. See [module level doc](crate::scope) for usage.
+#[derive(Hash)]
+pub struct LogPerTest {
+    /// max log level in this sink
+    pub level: Level,
+
+    pub format: LogFormat,
+
+    pub output: PerTestOutput,
+}
+
+impl LogPerTest {
+    /// Write logs of each test case to `<dir>/<scope name>.log` (appending).
+    pub fn dir<P: Into<PathBuf>>(dir: P, level: Level, format: LogFormat) -> Self {
+        Self { level, format, output: PerTestOutput::Dir(dir.into().into_boxed_path()) }
+    }
+
+    /// Only print the logs of a test case to console when it fails.
+    pub fn on_failure(target: ConsoleTarget, level: Level, format: LogFormat) -> Self {
+        Self { level, format, output: PerTestOutput::OnFailure(target) }
+    }
+
+    /// Only append the logs of a test case to the file (or a named pipe) when it fails.
+    pub fn on_failure_file<P: Into<PathBuf>>(path: P, level: Level, format: LogFormat) -> Self {
+        Self { level, format, output: PerTestOutput::OnFailureFile(path.into().into_boxed_path()) }
+    }
+}
+
+impl SinkConfigBuild for LogPerTest {
+    fn build(&self, _builder: &Builder) -> std::io::Result<LogSink> {
+        Ok(LogSink::PerTest(LogSinkPerTest::new(self)?))
+    }
+}
+
+impl SinkConfigTrait for LogPerTest {
+    fn get_level(&self) -> Level {
+        self.level
+    }
+
+    fn get_file_path(&self) -> Option<Box<Path>> {
+        None
+    }
+
+    fn write_hash(&self, hasher: &mut Box<dyn Hasher>) {
+        self.hash(hasher);
+        hasher.write(b"LogPerTest");
+    }
+}
+
+pub(crate) struct LogSinkPerTest {
+    max_level: Level,
+    formatter: LogFormat,
+    dir: Option<PathBuf>,
+    target_fd: libc::c_int,
+    /// Owns target_fd for PerTestOutput::OnFailureFile
+    _target_file: Option<File>,
+    files: Mutex<HashMap<Arc<str>, Arc<File>>>,
+    captured: Mutex<HashMap<Arc<str>, Vec<String>>>,
+}
+
+impl LogSinkPerTest {
+    fn new(config: &LogPerTest) -> std::io::Result<Self> {
+        let mut target_file = None;
+        let (dir, target_fd) = match &config.output {
+            PerTestOutput::Dir(dir) => (Some(dir.to_path_buf()), -1),
+            PerTestOutput::OnFailure(target) => (None, *target as libc::c_int),
+            PerTestOutput::OnFailureFile(path) => {
+                let f = open_file(path)?;
+                let fd = f.as_raw_fd();
+                target_file = Some(f);
+                (None, fd)
+            }
+        };
+        Ok(Self {
+            max_level: config.level,
+            formatter: config.format.clone(),
+            dir,
+            target_fd,
+            _target_file: target_file,
+            files: Mutex::new(HashMap::new()),
+            captured: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn get_file(&self, dir: &Path, scope: Option<Arc<str>>) -> Option<Arc<File>> {
+        let name = scope.unwrap_or_else(|| UNSCOPED_NAME.into());
+        let mut files = self.files.lock();
+        if let Some(f) = files.get(&name) {
+            return Some(f.clone());
+        }
+        let path = dir.join(format!("{}.log", name.replace('/', "_")));
+        match open_file(&path) {
+            Ok(f) => {
+                let f = Arc::new(f);
+                files.insert(name, f.clone());
+                Some(f)
+            }
+            Err(e) => {
+                report_error(SinkErrorKind::Open, &path.to_string_lossy(), &e);
+                None
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn write_fd(fd: libc::c_int, buf: &[u8]) {
+    unsafe {
+        let _ = libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len());
+    }
+}
+
+impl LogSinkTrait for LogSinkPerTest {
+    fn open(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.dir.as_ref() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    fn reopen(&self) -> std::io::Result<()> {
+        self.files.lock().clear();
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn log(&self, now: &Timer, ctx: &RecordContext, r: &Record) {
+        if r.level() <= self.max_level {
+            let scope = current_scope();
+            if let Some(dir) = self.dir.as_ref() {
+                if let Some(file) = self.get_file(dir, scope) {
+                    let buf = self.formatter.process(now, ctx, r);
+                    write_fd(file.as_raw_fd(), buf.as_bytes());
+                }
+            } else if let Some(name) = scope {
+                let buf = self.formatter.process(now, ctx, r);
+                self.captured.lock().entry(name).or_default().push(buf);
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn flush(&self) {}
+
+    fn scope_end(&self, scope: &Arc<str>, failed: bool) {
+        if self.dir.is_some() {
+            self.files.lock().remove(scope);
+            return;
+        }
+        let lines = self.captured.lock().remove(scope);
+        if failed {
+            if let Some(lines) = lines {
+                write_fd(self.target_fd, format!("---- log of {} ----\n", scope).as_bytes());
+                for line in lines {
+                    write_fd(self.target_fd, line.as_bytes());
+                }
+            }
+        }
+    }
+}
@@ -20,6 +20,8 @@
 //! On remote syslog server failure, will not panic, only "syslog: flush err" message will be print
 //! to stderr, the backend thread will automatically reconnect to server.
 //! In order to prevent hang up, the message will be dropped after a timeout.
+//! To keep them, wrap the sink with [LogFailover](crate::LogFailover), which routes the messages
+//! to another sink after the timeout, and probes the server for recovery.
 //!
 //! ## connect to local server
 //!
@@ -36,8 +38,11 @@
 //! ```
 
 use crate::{
-    config::{SinkConfigBuild, SinkConfigTrait},
+    config::{Builder, SinkConfigBuild, SinkConfigTrait},
+    formatter::RecordContext,
     log_impl::{LogSink, LogSinkTrait},
+    metrics::{SinkMetrics, SinkStats},
+    report::{report_error, report_info, SinkErrorKind},
     time::Timer,
 };
 use crossfire::*;
@@ -47,7 +52,10 @@ use std::io::{BufWriter, Error, ErrorKind, Result, Write};
 use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
 use std::os::unix::net::{UnixDatagram, UnixStream};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Once};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Once,
+};
 use std::thread;
 use std::time::{Duration, Instant};
 pub use syslog::Facility;
@@ -167,8 +175,8 @@ impl Syslog {
 }
 
 impl SinkConfigBuild for Syslog {
-    fn build(&self) -> LogSink {
-        LogSink::Syslog(LogSinkSyslog::new(self))
+    fn build(&self, builder: &Builder) -> std::io::Result<LogSink> {
+        Ok(LogSink::Syslog(LogSinkSyslog::new(self, builder.latency_enabled())))
     }
 }
 
@@ -190,16 +198,24 @@ impl SinkConfigTrait for Syslog {
 enum Msg {
     Line(Vec<u8>),
     Flush(Arc<Once>),
+    /// Try to reconnect if not connected
+    Probe,
 }
 
 pub(crate) struct LogSinkSyslog {
     tx: MTx<mpsc::Array<Msg>>,
     format: Formatter3164,
     max_level: Level,
+    /// Set by the backend thread, false when given up after timeout.
+    healthy: Arc<AtomicBool>,
+    /// The server address
+    name: String,
+    /// Shared with the backend thread
+    metrics: Arc<SinkMetrics>,
 }
 
 impl LogSinkSyslog {
-    fn new(config: &Syslog) -> Self {
+    fn new(config: &Syslog, timed: bool) -> Self {
         let (tx, rx) = mpsc::bounded_blocking(256);
 
         macro_rules! fill_format {
@@ -223,13 +239,22 @@ impl LogSinkSyslog {
         if timeout == Duration::from_secs(0) {
             timeout = TIMEOUT_DEFAULT;
         }
-        let mut backend = Backend { server: config.server.clone(), timeout, writer: None };
+        let healthy = Arc::new(AtomicBool::new(true));
+        let metrics = Arc::new(SinkMetrics::new(timed));
+        let mut backend = Backend {
+            server: config.server.clone(),
+            timeout,
+            writer: None,
+            healthy: healthy.clone(),
+            metrics: metrics.clone(),
+        };
         let _ = backend.reinit();
+        let name = backend.name();
 
         let mut f = Formatter3164::default();
         fill_format!(f, config);
         thread::spawn(move || backend.run(rx));
-        Self { tx, max_level: config.level, format: f }
+        Self { tx, max_level: config.level, format: f, healthy, name, metrics }
     }
 }
 
@@ -243,9 +268,10 @@ impl LogSinkTrait for LogSinkSyslog {
     }
 
     #[inline(always)]
-    fn log(&self, _now: &Timer, r: &Record) {
+    fn log(&self, _now: &Timer, ctx: &RecordContext, r: &Record) {
         let l = r.level();
         if r.level() <= self.max_level {
+            self.metrics.on_accept();
             let mut buf = Vec::with_capacity(128);
             let _level = match l {
                 Level::Trace => Severity::LOG_DEBUG, // syslog don't have trace level
@@ -254,7 +280,10 @@ impl LogSinkTrait for LogSinkSyslog {
                 Level::Warn => Severity::LOG_WARNING,
                 Level::Error => Severity::LOG_ERR,
             };
-            let msg = format!("{}", r.args());
+            let mut msg = format!("{}", r.args());
+            for (k, v) in ctx.fields {
+                push_sd_param(&mut msg, k, v);
+            }
             self.format.format(&mut buf, _level, msg).expect("format");
             let _ = self.tx.send(Msg::Line(buf));
         }
@@ -267,12 +296,31 @@ impl LogSinkTrait for LogSinkSyslog {
             o.wait();
         }
     }
+
+    #[inline(always)]
+    fn healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    fn stats(&self, out: &mut Vec<SinkStats>) {
+        out.push(self.metrics.snapshot(self.name.clone(), self.tx.len() as u64));
+    }
+
+    /// Reconnect in the backend thread, the result is known asynchronously.
+    fn probe(&self) -> bool {
+        if !self.healthy() {
+            let _ = self.tx.try_send(Msg::Probe);
+        }
+        self.healthy()
+    }
 }
 
 struct Backend {
     server: Option<SyslogAddr>,
     writer: Option<SyslogBackend>,
     timeout: Duration,
+    healthy: Arc<AtomicBool>,
+    metrics: Arc<SinkMetrics>,
 }
 
 impl Backend {
@@ -281,13 +329,13 @@ impl Backend {
         let sock = UnixDatagram::unbound()?;
         match sock.connect(Path::new(path)) {
             Ok(()) => {
-                println!("syslog: connect to unix {:?}", path);
+                report_info(format_args!("syslog: connect to unix {:?}", path));
                 return Ok(SyslogBackend::Unix(sock));
             }
             Err(e) => {
                 if e.raw_os_error() == Some(libc::EPROTOTYPE) {
                     let sock = UnixStream::connect(path)?;
-                    println!("syslog: connect to unix {:?}", path);
+                    report_info(format_args!("syslog: connect to unix {:?}", path));
                     return Ok(SyslogBackend::UnixStream(BufWriter::new(sock)));
                 }
                 return Err(e);
@@ -311,7 +359,7 @@ impl Backend {
                 .next()
                 .ok_or_else(|| Error::new(ErrorKind::NotFound, "syslog: no server address").into())
         })?;
-        println!("syslog: connect to udp {:?}", remote);
+        report_info(format_args!("syslog: connect to udp {:?}", remote));
         let socket = UdpSocket::bind(local)?;
         return Ok(SyslogBackend::Udp(socket, server_addr));
     }
@@ -335,11 +383,21 @@ impl Backend {
         }
     }
 
+    /// The server address for reporting error
+    fn name(&self) -> String {
+        match &self.server {
+            Some(SyslogAddr::Unix(p)) => p.display().to_string(),
+            Some(SyslogAddr::UDP(_, remote)) => remote.clone(),
+            Some(SyslogAddr::TCP(remote)) => remote.clone(),
+            None => "syslog".to_string(),
+        }
+    }
+
     #[inline(always)]
     fn reinit(&mut self) -> Result<()> {
         match Self::connect(&self.server, self.timeout) {
             Err(e) => {
-                eprintln!("syslog: connect {:?} err {:?}", e, self.server);
+                report_error(SinkErrorKind::Connect, &self.name(), &e);
                 return Err(e);
             }
             Ok(backend) => {
@@ -353,7 +411,7 @@ impl Backend {
     fn flush(&mut self) {
         if let Some(writer) = self.writer.as_mut() {
             if let Err(e) = writer.flush() {
-                eprintln!("syslog: flush err {:?}", e);
+                report_error(SinkErrorKind::Flush, &self.name(), &e);
                 self.writer = None;
             }
         }
@@ -362,10 +420,15 @@ impl Backend {
     #[inline]
     fn write(&mut self, msg: &[u8]) {
         if let Some(writer) = self.writer.as_mut() {
+            let start = self.metrics.start();
             match writer.write_all(msg) {
-                Ok(_) => return,
+                Ok(_) => {
+                    self.metrics.on_write(msg.len(), start);
+                    return;
+                }
                 Err(e) => {
-                    eprintln!("syslog: write err {:?}", e);
+                    self.metrics.on_error();
+                    report_error(SinkErrorKind::Write, &self.name(), &e);
                     self.writer = None;
                 }
             }
@@ -375,10 +438,16 @@ impl Backend {
             thread::sleep(Duration::from_millis(500));
             if self.reinit().is_ok() {
                 if let Some(writer) = self.writer.as_mut() {
+                    let start = self.metrics.start();
                     match writer.write_all(msg) {
-                        Ok(_) => return,
+                        Ok(_) => {
+                            self.metrics.on_write(msg.len(), start);
+                            self.healthy.store(true, Ordering::Release);
+                            return;
+                        }
                         Err(e) => {
-                            eprintln!("syslog: write err {:?}", e);
+                            self.metrics.on_error();
+                            report_error(SinkErrorKind::Write, &self.name(), &e);
                             self.writer = None;
                         }
                     }
@@ -386,11 +455,19 @@ impl Backend {
             }
             if Instant::now().duration_since(start_ts) > self.timeout {
                 // give up
+                self.metrics.on_drop();
+                self.healthy.store(false, Ordering::Release);
                 return;
             }
         }
     }
 
+    fn probe(&mut self) {
+        if self.writer.is_none() && self.reinit().is_ok() {
+            self.healthy.store(true, Ordering::Release);
+        }
+    }
+
     fn run(&mut self, rx: Rx<mpsc::Array<Msg>>) {
         loop {
             match rx.recv() {
@@ -405,6 +482,7 @@ impl Backend {
                                 o.call_once(|| {});
                                 need_flush = false;
                             }
+                            Msg::Probe => self.probe(),
                         }
                     }
                     if need_flush {
@@ -415,6 +493,7 @@ impl Backend {
                     self.flush();
                     o.call_once(|| {});
                 }
+                Ok(Msg::Probe) => self.probe(),
                 Err(_) => {
                     self.flush();
                     // exit
@@ -424,3 +503,38 @@ impl Backend {
         }
     }
 }
+
+/// Append a field as ` name="value"`, quoted like the SD-PARAM of RFC 5424:
+/// `"`, `\` and `]` in the value are escaped with a backslash, and the chars not allowed in
+/// PARAM-NAME are replaced with `_`.
+fn push_sd_param(msg: &mut String, name: &str, value: &str) {
+    msg.push(' ');
+    for c in name.chars() {
+        if c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"') {
+            msg.push(c);
+        } else {
+            msg.push('_');
+        }
+    }
+    msg.push_str("=\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            msg.push('\\');
+        }
+        msg.push(c);
+    }
+    msg.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sd_param() {
+        let mut msg = "hello".to_string();
+        push_sd_param(&mut msg, "host", "a b");
+        push_sd_param(&mut msg, "my key]", r#"x"y\z]"#);
+        assert_eq!(msg, r#"hello host="a b" my_key_="x\"y\\z\]""#);
+    }
+}
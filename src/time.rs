@@ -1,5 +1,15 @@
-use chrono::{DateTime, Local};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use std::hash::{Hash, Hasher};
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+/// Time of the record, deref to the local time.
+///
+/// Use [Timer::utc()] or [Timer::with_zone()] for the time in other timezone, for example
+/// the one configured by [LogFormat::timezone()](crate::LogFormat::timezone()).
 pub struct Timer(DateTime<Local>);
 
 impl std::ops::Deref for Timer {
@@ -11,7 +21,160 @@ impl std::ops::Deref for Timer {
 }
 
 impl Timer {
-    pub(crate) fn new() -> Self {
-        return Self(Local::now());
+    #[inline(always)]
+    pub(crate) fn new(clock: &Clock) -> Self {
+        match clock {
+            Clock::Real => Self(Local::now()),
+            _ => Self(clock.now().into()),
+        }
+    }
+
+    /// Restore the timer of a record captured in the logging thread.
+    #[inline(always)]
+    pub(crate) fn deferred(time: DateTime<Local>) -> Self {
+        Self(time)
+    }
+
+    /// The time in UTC
+    #[inline(always)]
+    pub fn utc(&self) -> DateTime<Utc> {
+        self.0.with_timezone(&Utc)
+    }
+
+    /// The time in the timezone
+    #[inline]
+    pub fn with_zone(&self, tz: TimeZone) -> DateTime<FixedOffset> {
+        match tz {
+            TimeZone::Local => self.0.fixed_offset(),
+            TimeZone::Utc => self.0.with_timezone(&Utc).fixed_offset(),
+            TimeZone::Fixed(offset) => self.0.with_timezone(&offset),
+        }
+    }
+}
+
+/// The timezone to display the time of records, configured by
+/// [LogFormat::timezone()](crate::LogFormat::timezone()).
+#[derive(Clone, Copy, Default, Hash, PartialEq, Debug)]
+pub enum TimeZone {
+    /// The local timezone of the host (lookup for each record)
+    #[default]
+    Local,
+    /// UTC, which is recommended when merging logs from hosts in different zones.
+    Utc,
+    /// A fixed offset from UTC, for example `FixedOffset::east_opt(8 * 3600).unwrap()`
+    Fixed(FixedOffset),
+}
+
+/// The source of time for the log records and age-based rotation, configured by
+/// [Builder::clock()](crate::Builder::clock()).
+///
+/// For snapshot tests of log output, or testing rotation without waiting.
+///
+/// # Example
+///
+/// ``` rust
+/// use captains_log::*;
+/// use std::sync::Arc;
+/// use std::time::{Duration, SystemTime};
+///
+/// let clock = Arc::new(ManualClock::new(SystemTime::now()));
+/// recipe::raw_file_logger("/tmp/test_clock.log", Level::Debug)
+///     .clock(Clock::Manual(clock.clone()))
+///     .test().build().expect("setup log");
+/// info!("before");
+/// clock.advance(Duration::from_secs(3600));
+/// info!("one hour later");
+/// ```
+#[derive(Clone, Default)]
+pub enum Clock {
+    /// The system clock
+    #[default]
+    Real,
+    /// Always return the same time
+    Fixed(SystemTime),
+    /// Time only changed by [ManualClock::advance()] or [ManualClock::set()]
+    Manual(Arc<ManualClock>),
+}
+
+impl Clock {
+    #[inline]
+    pub fn now(&self) -> SystemTime {
+        match self {
+            Self::Real => SystemTime::now(),
+            Self::Fixed(t) => *t,
+            Self::Manual(c) => c.now(),
+        }
+    }
+}
+
+impl Hash for Clock {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        match self {
+            Self::Real => hasher.write_u8(0),
+            Self::Fixed(t) => {
+                hasher.write_u8(1);
+                t.hash(hasher);
+            }
+            Self::Manual(c) => {
+                hasher.write_u8(2);
+                hasher.write_usize(Arc::as_ptr(c) as usize);
+            }
+        }
+    }
+}
+
+impl TimeZone {
+    /// Format the time in this timezone
+    #[inline]
+    pub(crate) fn format(&self, t: &DateTime<Utc>, fmt: &str) -> String {
+        match self {
+            Self::Local => t.with_timezone(&Local).format(fmt).to_string(),
+            Self::Utc => t.format(fmt).to_string(),
+            Self::Fixed(offset) => t.with_timezone(offset).format(fmt).to_string(),
+        }
+    }
+}
+
+/// A clock that only advanced manually. Share it in Arc with [Clock::Manual].
+pub struct ManualClock {
+    /// nanoseconds since UNIX_EPOCH
+    nanos: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self { nanos: AtomicI64::new(to_nanos(start)) }
+    }
+
+    #[inline]
+    pub fn now(&self) -> SystemTime {
+        from_nanos(self.nanos.load(Ordering::Acquire))
+    }
+
+    #[inline]
+    pub fn set(&self, t: SystemTime) {
+        self.nanos.store(to_nanos(t), Ordering::Release);
+    }
+
+    #[inline]
+    pub fn advance(&self, d: Duration) {
+        self.nanos.fetch_add(d.as_nanos() as i64, Ordering::AcqRel);
+    }
+}
+
+#[inline]
+fn to_nanos(t: SystemTime) -> i64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos() as i64,
+        Err(e) => -(e.duration().as_nanos() as i64),
+    }
+}
+
+#[inline]
+fn from_nanos(nanos: i64) -> SystemTime {
+    if nanos >= 0 {
+        UNIX_EPOCH + Duration::from_nanos(nanos as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_nanos(nanos.unsigned_abs())
     }
 }
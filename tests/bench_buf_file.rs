@@ -0,0 +1,88 @@
+use captains_log::*;
+use std::fs::*;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::*;
+
+const THREADS: usize = 4;
+
+const ROUNDS: usize = 20000;
+
+/// Returns the time spent in the logging threads, and the total time until flushed.
+fn run_bench(deferred: bool, mode: WriteMode) -> (Duration, Duration) {
+    let _ = remove_file("/tmp/log_bench_buf_file.log");
+    let mut file = LogBufFile::new(
+        "/tmp",
+        "log_bench_buf_file.log",
+        Level::Debug,
+        recipe::LOG_FORMAT_THREADED_DEBUG,
+        0,
+    )
+    .backpressure(Backpressure::Block, ROUNDS * THREADS)
+    .write_mode(mode);
+    if deferred {
+        file = file.deferred_format();
+    }
+    let mut builder = Builder::default().add_sink(file);
+    builder.dynamic = true;
+    builder.build().expect("setup_log");
+
+    let start = Instant::now();
+    let mut ths = Vec::new();
+    for _ in 0..THREADS {
+        ths.push(std::thread::spawn(|| {
+            let start = Instant::now();
+            for i in 0..ROUNDS {
+                info!("bench {} {:?} {}", i, Instant::now(), "some text to format");
+            }
+            start.elapsed()
+        }));
+    }
+    let mut logging = Duration::ZERO;
+    for th in ths {
+        logging += th.join().unwrap();
+    }
+    log::logger().flush();
+    (logging / THREADS as u32, start.elapsed())
+}
+
+/// Run with `make bench bench_buf_file_deferred_format`
+#[test]
+fn bench_buf_file_deferred_format() {
+    lock_file!();
+    for deferred in [false, true] {
+        let (logging, total) = run_bench(deferred, WriteMode::default());
+        println!(
+            "deferred_format={}: {} records, logging threads {:?} ({:?}/record), total {:?}",
+            deferred,
+            ROUNDS * THREADS,
+            logging,
+            logging / ROUNDS as u32,
+            total
+        );
+    }
+}
+
+/// Run with `make bench bench_buf_file_write_mode`
+#[test]
+fn bench_buf_file_write_mode() {
+    lock_file!();
+    for mode in [
+        WriteMode::Copy,
+        WriteMode::Vectored,
+        #[cfg(feature = "io_uring")]
+        WriteMode::IoUring,
+    ] {
+        let (_, total) = run_bench(true, mode);
+        let content = read_to_string("/tmp/log_bench_buf_file.log").expect("read log");
+        assert_eq!(content.lines().count(), ROUNDS * THREADS);
+        println!(
+            "{:?}: {} records, total {:?} ({:.0} records/s)",
+            mode,
+            ROUNDS * THREADS,
+            total,
+            (ROUNDS * THREADS) as f64 / total.as_secs_f64()
+        );
+    }
+}
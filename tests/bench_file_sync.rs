@@ -0,0 +1,58 @@
+use captains_log::*;
+use std::fs::*;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::*;
+
+const ROUNDS: usize = 2000;
+
+fn run_bench(buffered: bool, policy: SyncPolicy) -> Duration {
+    let _ = remove_file("/tmp/log_bench_file_sync.log");
+    let format = recipe::LOG_FORMAT_DEBUG;
+    let mut builder = if buffered {
+        let file = LogBufFile::new("/tmp", "log_bench_file_sync.log", Level::Debug, format, 0)
+            .sync_policy(policy);
+        Builder::default().add_sink(file)
+    } else {
+        let file = LogRawFile::new("/tmp", "log_bench_file_sync.log", Level::Debug, format)
+            .sync_policy(policy);
+        Builder::default().add_sink(file)
+    };
+    builder.dynamic = true;
+    builder.build().expect("setup_log");
+
+    let start = Instant::now();
+    for i in 0..ROUNDS {
+        info!("bench {} {}", i, "some text to format");
+    }
+    log::logger().flush();
+    let elapsed = start.elapsed();
+    let content = read_to_string("/tmp/log_bench_file_sync.log").expect("read log");
+    assert_eq!(content.lines().count(), ROUNDS);
+    elapsed
+}
+
+/// Run with `make bench bench_file_sync_policy`
+#[test]
+fn bench_file_sync_policy() {
+    lock_file!();
+    for buffered in [false, true] {
+        for policy in [
+            SyncPolicy::None,
+            SyncPolicy::Periodic(Duration::from_millis(100)),
+            SyncPolicy::PerFlush,
+            SyncPolicy::PerRecord,
+        ] {
+            let elapsed = run_bench(buffered, policy);
+            println!(
+                "buffered={} {:?}: {} records, {:?} ({:?}/record)",
+                buffered,
+                policy,
+                ROUNDS,
+                elapsed,
+                elapsed / ROUNDS as u32
+            );
+        }
+    }
+}
@@ -340,3 +340,528 @@ fn test_global_log_debug_assert_eq_with_msg() {
         assert_eq!(debug_logs.len(), 0);
     }
 }
+
+#[test]
+fn test_global_log_timezone() {
+    lock_file!();
+
+    let file_path = "/tmp/log_test_tz.log";
+    let _ = remove_file(file_path);
+    let t = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_123);
+    let offset = chrono::FixedOffset::east_opt(8 * 3600).unwrap();
+    Builder::default()
+        .clock(Clock::Fixed(t))
+        .add_sink(LogRawFile::new(
+            "/tmp",
+            "log_test_tz.log",
+            Level::Debug,
+            recipe::LOG_FORMAT_RFC3339_UTC,
+        ))
+        .add_sink(LogRawFile::new(
+            "/tmp",
+            "log_test_tz.log",
+            Level::Debug,
+            recipe::LOG_FORMAT_DEBUG.timezone(TimeZone::Fixed(offset)),
+        ))
+        .add_sink(LogRawFile::new(
+            "/tmp",
+            "log_test_tz.log",
+            Level::Debug,
+            recipe::LOG_FORMAT_EPOCH_MILLIS,
+        ))
+        .test()
+        .build()
+        .expect("setup_log");
+    info!("tz");
+    let logs = parse_log(file_path, RE_DEBUG).expect("parse log");
+    assert_eq!(logs.len(), 3);
+    assert_eq!(logs[0][1], "2023-11-14T22:13:20.123000+00:00");
+    assert_eq!(logs[1][1], "2023-11-15 06:13:20.123000");
+    assert_eq!(logs[2][1], "1700000000123");
+    let _ = remove_file(file_path);
+}
+
+fn fields_format_f(r: FormatRecord) -> String {
+    let seq = r.seq();
+    let target = r.target();
+    let module = r.module_path();
+    let thread = r.thread_name().unwrap_or("<unnamed>");
+    let pid = r.pid();
+    let host = r.hostname();
+    let msg = r.msg();
+    format!("[{seq}][{target}][{module}][{thread}][{pid}][{host}] {msg}\n").to_string()
+}
+
+#[test]
+fn test_global_log_format_fields() {
+    lock_file!();
+
+    let file_path = "/tmp/log_test_fields.log";
+    let _ = remove_file(file_path);
+    recipe::raw_file_logger_custom(file_path, Level::Debug, "", fields_format_f)
+        .test()
+        .build()
+        .expect("setup_log");
+    info!(target: "my_target", "first");
+    info!("second");
+    // The pid cached before fork is not inherited
+    let child = unsafe { libc::fork() };
+    if child == 0 {
+        info!("child");
+        unsafe { libc::_exit(0) };
+    }
+    assert!(child > 0);
+    unsafe { libc::waitpid(child, std::ptr::null_mut(), 0) };
+    let logs = parse_log(file_path, r"^\[(\d+)\]\[(.+)\]\[(.+)\]\[(.+)\]\[(\d+)\]\[(.+)\] (.+)$")
+        .expect("parse log");
+    assert_eq!(logs.len(), 3);
+    assert_eq!(logs[2][5], child.to_string());
+    assert_eq!(logs[2][7], "child");
+    let seq0: u64 = logs[0][1].parse().unwrap();
+    let seq1: u64 = logs[1][1].parse().unwrap();
+    assert!(seq1 > seq0);
+    assert_eq!(logs[0][2], "my_target");
+    assert_eq!(logs[1][2], "global_log");
+    assert_eq!(logs[0][3], "global_log");
+    assert_eq!(logs[0][4], "test_global_log_format_fields");
+    assert_eq!(logs[0][5], std::process::id().to_string());
+    assert!(!logs[0][6].is_empty());
+    assert_eq!(logs[1][7], "second");
+    let _ = remove_file(file_path);
+}
+
+fn field_format_f(r: FormatRecord) -> String {
+    let service = r.field("service").unwrap_or("-");
+    let region = r.field("region").unwrap_or("-");
+    let msg = r.msg();
+    format!("[{service}][{region}] {msg}\n").to_string()
+}
+
+#[test]
+fn test_global_log_static_fields() {
+    lock_file!();
+
+    let file_path = "/tmp/log_test_fields_static.log";
+    let json_path = "/tmp/log_test_fields_json.log";
+    let _ = remove_file(file_path);
+    let _ = remove_file(json_path);
+    recipe::raw_file_logger_custom(file_path, Level::Debug, "", field_format_f)
+        .add_sink(LogRawFile::new(
+            "/tmp",
+            "log_test_fields_json.log",
+            Level::Debug,
+            recipe::LOG_FORMAT_JSON,
+        ))
+        .with_field("service", "billing")
+        .with_field("region", "us")
+        .with_field("region", "eu")
+        .with_field("level", "reserved")
+        .test()
+        .build()
+        .expect("setup_log");
+    info!("with \"quote\"");
+    let logs = parse_log(file_path, r"^\[(.+)\]\[(.+)\] (.+)$").expect("parse log");
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0][1], "billing");
+    assert_eq!(logs[0][2], "eu");
+    let json = read_to_string(json_path).expect("read json");
+    assert!(json.starts_with(r#"{"time":""#));
+    assert!(json.ends_with(
+        r#","msg":"with \"quote\"","fields":{"service":"billing","region":"eu","level":"reserved"}}
+"#
+    ));
+    let _ = remove_file(file_path);
+    let _ = remove_file(json_path);
+}
+
+fn check_buf_file_backpressure(policy: Backpressure) {
+    let _ = std::fs::remove_file("/tmp/log_test_backpressure.log");
+    let file = LogBufFile::new(
+        "/tmp",
+        "log_test_backpressure.log",
+        Level::Debug,
+        recipe::LOG_FORMAT_DEBUG,
+        0,
+    )
+    .backpressure(policy, 1);
+    let mut builder = Builder::default().add_sink(file);
+    builder.dynamic = true;
+    let logger = builder.build().expect("setup_log");
+    let total = 20000;
+    for i in 0..total {
+        info!("test {}", i);
+    }
+    log::logger().flush();
+    let dropped = logger.dropped();
+    assert!(dropped > 0);
+    let logs = parse_log("/tmp/log_test_backpressure.log", RE_DEBUG).expect("parse log");
+    let reports: Vec<_> = logs.iter().filter(|l| l[2] == "WARN").collect();
+    assert!(reports.len() > 0);
+    let mut reported = 0;
+    for report in reports.iter() {
+        let n: &str = report[5].split(' ').next().unwrap();
+        reported += n.parse::<u64>().unwrap();
+    }
+    assert_eq!(reported, dropped);
+    assert_eq!(logs.len() - reports.len() + dropped as usize, total);
+}
+
+#[test]
+fn test_global_log_buf_file_backpressure_drop_newest() {
+    lock_file!();
+    check_buf_file_backpressure(Backpressure::DropNewest);
+}
+
+#[test]
+fn test_global_log_buf_file_backpressure_drop_oldest() {
+    lock_file!();
+    check_buf_file_backpressure(Backpressure::DropOldest);
+}
+
+fn deferred_format_f(r: FormatRecord) -> String {
+    let level = r.level();
+    let file = r.file();
+    let line = r.line();
+    let msg = r.msg();
+    let thread = r.thread_name().unwrap_or("<unnamed>");
+    let key = r.key("user");
+    let field = r.field("app").unwrap_or("");
+    format!("[{level}][{file}:{line}][{thread}][{field}] {msg}{key}\n").to_string()
+}
+
+#[test]
+fn test_global_log_buf_file_deferred_format() {
+    lock_file!();
+    let _ = std::fs::remove_file("/tmp/log_test_deferred.log");
+    let format = LogFormat::new(recipe::DEFAULT_TIME, deferred_format_f);
+    let file =
+        LogBufFile::new("/tmp", "log_test_deferred.log", Level::Debug, format, 0).deferred_format();
+    let mut builder = Builder::default().add_sink(file).with_field("app", "demo");
+    builder.dynamic = true;
+    builder.build().expect("setup_log");
+    let line = line!();
+    info!("static msg");
+    info!(user = "alice"; "test {}", 1);
+    log::logger().flush();
+    let content = std::fs::read_to_string("/tmp/log_test_deferred.log").expect("read log");
+    let lines: Vec<&str> = content.lines().collect();
+    let thread = std::thread::current().name().unwrap().to_string();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], format!("[INFO][global_log.rs:{}][{thread}][demo] static msg", line + 1));
+    assert_eq!(
+        lines[1],
+        format!("[INFO][global_log.rs:{}][{thread}][demo] test 1 (alice)", line + 2)
+    );
+}
+
+fn check_buf_file_flush_level(sync: bool) {
+    let _ = std::fs::remove_file("/tmp/log_test_flush_level.log");
+    let file = LogBufFile::new(
+        "/tmp",
+        "log_test_flush_level.log",
+        Level::Debug,
+        recipe::LOG_FORMAT_DEBUG,
+        1000,
+    )
+    .flush_level(Level::Error, sync);
+    let mut builder = Builder::default().add_sink(file);
+    builder.dynamic = true;
+    builder.build().expect("setup_log");
+    info!("before error");
+    error!("error {}", sync);
+    // Without waiting for flush_millis
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let logs = parse_log("/tmp/log_test_flush_level.log", RE_DEBUG).expect("parse log");
+    assert_eq!(logs.len(), 2);
+    assert_eq!(logs[0][5], "before error");
+    assert_eq!(logs[1][5], format!("error {}", sync));
+}
+
+#[test]
+fn test_global_log_buf_file_flush_level_async() {
+    lock_file!();
+    check_buf_file_flush_level(false);
+}
+
+#[test]
+fn test_global_log_buf_file_flush_level_sync() {
+    lock_file!();
+    check_buf_file_flush_level(true);
+}
+
+fn check_buf_file_write_mode(mode: WriteMode) {
+    let path = format!("/tmp/log_test_write_mode_{:?}.log", mode);
+    let _ = std::fs::remove_file(&path);
+    let name = path.strip_prefix("/tmp/").unwrap();
+    let mut file = LogBufFile::new("/tmp", name, Level::Debug, recipe::LOG_FORMAT_DEBUG, 1000)
+        .write_mode(mode);
+    // Allow more than IOV_MAX lines in one flush
+    file.flush_size = 1 << 20;
+    let mut builder = Builder::default().add_sink(file);
+    builder.dynamic = true;
+    builder.build().expect("setup_log");
+    let total = 3000;
+    for i in 0..total {
+        info!("write mode {}", i);
+    }
+    log::logger().flush();
+    let logs = parse_log(&path, RE_DEBUG).expect("parse log");
+    assert_eq!(logs.len(), total);
+    for (i, log) in logs.iter().enumerate() {
+        assert_eq!(log[5], format!("write mode {}", i));
+    }
+}
+
+#[test]
+fn test_global_log_buf_file_write_copy() {
+    lock_file!();
+    check_buf_file_write_mode(WriteMode::Copy);
+}
+
+#[test]
+fn test_global_log_buf_file_write_vectored() {
+    lock_file!();
+    check_buf_file_write_mode(WriteMode::Vectored);
+}
+
+#[cfg(feature = "io_uring")]
+#[test]
+fn test_global_log_buf_file_write_io_uring() {
+    lock_file!();
+    check_buf_file_write_mode(WriteMode::IoUring);
+}
+
+fn check_failover(buffered: bool) {
+    // Writing to /dev/full fails with ENOSPC
+    let _ = std::fs::remove_file("/tmp/log_test_failover.log");
+    let secondary =
+        LogRawFile::new("/tmp", "log_test_failover.log", Level::Debug, recipe::LOG_FORMAT_DEBUG);
+    let mut builder = if buffered {
+        let primary = LogBufFile::new("/dev", "full", Level::Debug, recipe::LOG_FORMAT_DEBUG, 0);
+        Builder::default().add_sink(LogFailover::new(primary, secondary))
+    } else {
+        let primary = LogRawFile::new("/dev", "full", Level::Debug, recipe::LOG_FORMAT_DEBUG);
+        Builder::default().add_sink(LogFailover::new(primary, secondary))
+    };
+    builder.dynamic = true;
+    builder.build().expect("setup_log");
+    info!("first");
+    // The buffered sink finds out the failure on writing
+    log::logger().flush();
+    info!("second");
+    log::logger().flush();
+    let logs = parse_log("/tmp/log_test_failover.log", RE_DEBUG).expect("parse log");
+    let msgs: Vec<&str> = logs.iter().map(|l| l[5].as_str()).collect();
+    assert!(msgs.contains(&"primary log sink is unhealthy, failover to secondary"));
+    if buffered {
+        // The lines failed to write in the background are handed over to the secondary, the
+        // notice might come after them.
+        let records: Vec<&str> =
+            msgs.iter().filter(|msg| !msg.starts_with("primary")).cloned().collect();
+        assert_eq!(records, ["first", "second"]);
+    } else {
+        assert_eq!(
+            msgs,
+            ["primary log sink is unhealthy, failover to secondary", "first", "second"]
+        );
+    }
+}
+
+#[test]
+fn test_global_log_failover_raw() {
+    lock_file!();
+    check_failover(false);
+}
+
+#[test]
+fn test_global_log_failover_buffered() {
+    lock_file!();
+    check_failover(true);
+}
+
+#[test]
+fn test_global_log_on_error() {
+    lock_file!();
+    let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let _errors = errors.clone();
+    // Writing to /dev/full fails with ENOSPC
+    let file = LogRawFile::new("/dev", "full", Level::Debug, recipe::LOG_FORMAT_DEBUG);
+    let mut builder = Builder::default()
+        .add_sink(file)
+        .on_error(move |e: &SinkError| {
+            _errors.lock().unwrap().push((e.kind, e.sink.to_string(), e.error.raw_os_error()));
+        })
+        .quiet();
+    builder.dynamic = true;
+    let logger = builder.build().expect("setup_log");
+    assert!(logger.healthy());
+    info!("first");
+    info!("second");
+    assert_eq!(logger.health(), [false]);
+    assert!(!logger.healthy());
+    // Reported once until recovered
+    assert_eq!(
+        *errors.lock().unwrap(),
+        [(SinkErrorKind::Write, "/dev/full".to_string(), Some(libc::ENOSPC))]
+    );
+    logger.reopen().expect("reopen");
+    assert_eq!(logger.health(), [true]);
+}
+
+fn check_metrics(buffered: bool) {
+    let dir = "/tmp/log_test_metrics";
+    let _ = std::fs::remove_dir_all(dir);
+    let rotation = rotation::Rotation::by_size(1024, None);
+    let exported = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+    let _exported = exported.clone();
+    let export = MetricsExport::callback(
+        move |text| *_exported.lock().unwrap() = text.to_string(),
+        std::time::Duration::from_millis(50),
+    );
+    let builder = if buffered {
+        let file = LogBufFile::new(dir, "metrics.log", Level::Debug, recipe::LOG_FORMAT_DEBUG, 0)
+            .rotation(rotation);
+        Builder::default().add_sink(file)
+    } else {
+        let file = LogRawFile::new(dir, "metrics.log", Level::Debug, recipe::LOG_FORMAT_DEBUG)
+            .rotation(rotation);
+        Builder::default().add_sink(file)
+    };
+    let mut builder = builder.export_metrics(export);
+    builder.dynamic = true;
+    let logger = builder.build().expect("setup_log");
+    for i in 0..100 {
+        info!("metrics {}", i);
+    }
+    debug!("{}", "x".repeat(100));
+    log::logger().flush();
+    let stats = logger.metrics();
+    assert_eq!(stats.len(), 1);
+    let st = &stats[0];
+    assert_eq!(st.name, format!("{}/metrics.log", dir));
+    assert_eq!(st.records, 101);
+    let mut size = 0;
+    for entry in read_dir(dir).unwrap() {
+        size += entry.unwrap().metadata().unwrap().len();
+    }
+    assert_eq!(st.bytes, size);
+    assert!(st.rotations > 0);
+    assert_eq!(st.dropped, 0);
+    assert_eq!(st.write_errors, 0);
+    assert!(st.flush_latency.count > 0);
+    assert_eq!(st.flush_latency.buckets.len(), LATENCY_BUCKETS.len());
+    if !buffered {
+        assert_eq!(st.flush_latency.count, 101);
+    }
+    let text = logger.metrics_prometheus();
+    assert!(
+        text.contains(&format!("captains_log_records_total{{sink=\"{}/metrics.log\"}} 101\n", dir))
+    );
+    assert!(text.contains("# TYPE captains_log_flush_latency_seconds histogram\n"));
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(exported.lock().unwrap().contains("captains_log_bytes_total"));
+}
+
+#[test]
+fn test_global_log_metrics_raw() {
+    lock_file!();
+    check_metrics(false);
+}
+
+#[test]
+fn test_global_log_metrics_buffered() {
+    lock_file!();
+    check_metrics(true);
+}
+
+#[test]
+fn test_global_log_metrics_latency() {
+    lock_file!();
+    let dir = "/tmp/log_test_metrics_latency";
+    let _ = std::fs::remove_dir_all(dir);
+    let sinks = || {
+        Builder::default()
+            .add_sink(LogRawFile::new(dir, "raw.log", Level::Debug, recipe::LOG_FORMAT_DEBUG))
+            .add_sink(LogBufFile::new(dir, "buf.log", Level::Debug, recipe::LOG_FORMAT_DEBUG, 0))
+    };
+    // Only the batched writes of LogBufFile are timed by default
+    let mut builder = sinks();
+    builder.dynamic = true;
+    let logger = builder.build().expect("setup_log");
+    info!("latency");
+    log::logger().flush();
+    let stats = logger.metrics();
+    assert_eq!(stats[0].records, 1);
+    assert!(stats[0].bytes > 0);
+    assert_eq!(stats[0].flush_latency.count, 0);
+    assert!(stats[1].flush_latency.count > 0);
+
+    let mut builder = sinks().metrics_latency();
+    builder.dynamic = true;
+    let logger = builder.build().expect("setup_log");
+    info!("latency");
+    log::logger().flush();
+    let stats = logger.metrics();
+    assert_eq!(stats[0].flush_latency.count, 1);
+    assert!(stats[1].flush_latency.count > 0);
+}
+
+fn check_auto_reopen(buffered: bool) {
+    let path = "/tmp/log_test_auto_reopen.log";
+    let moved = "/tmp/log_test_auto_reopen.log.moved";
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(moved);
+    let interval = std::time::Duration::from_millis(0);
+    let mut builder = if buffered {
+        let file = LogBufFile::new(
+            "/tmp",
+            "log_test_auto_reopen.log",
+            Level::Debug,
+            recipe::LOG_FORMAT_DEBUG,
+            0,
+        )
+        .auto_reopen(interval);
+        Builder::default().add_sink(file)
+    } else {
+        let file = LogRawFile::new(
+            "/tmp",
+            "log_test_auto_reopen.log",
+            Level::Debug,
+            recipe::LOG_FORMAT_DEBUG,
+        )
+        .auto_reopen(interval);
+        Builder::default().add_sink(file)
+    };
+    builder.dynamic = true;
+    builder.build().expect("setup_log");
+    info!("before move");
+    log::logger().flush();
+    std::fs::rename(path, moved).expect("move");
+    info!("after move");
+    log::logger().flush();
+    let logs = parse_log(moved, RE_DEBUG).expect("parse log");
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0][5], "before move");
+    let logs = parse_log(path, RE_DEBUG).expect("parse log");
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0][5], "after move");
+
+    std::fs::remove_file(path).expect("delete");
+    info!("after delete");
+    log::logger().flush();
+    let logs = parse_log(path, RE_DEBUG).expect("parse log");
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0][5], "after delete");
+}
+
+#[test]
+fn test_global_log_auto_reopen_raw() {
+    lock_file!();
+    check_auto_reopen(false);
+}
+
+#[test]
+fn test_global_log_auto_reopen_buffered() {
+    lock_file!();
+    check_auto_reopen(true);
+}
@@ -0,0 +1,94 @@
+use captains_log::{scope::LogPerTest, *};
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::Once;
+use std::thread;
+
+const TEST_DIR: &str = "/tmp/captains_log_per_test";
+const FAILURE_LOG: &str = "/tmp/captains_log_per_test_failure.log";
+
+static INIT: Once = Once::new();
+
+fn setup() {
+    INIT.call_once(|| {
+        let _ = std::fs::remove_dir_all(TEST_DIR);
+        let _ = std::fs::remove_file(FAILURE_LOG);
+        let on_failure =
+            LogPerTest::on_failure_file(FAILURE_LOG, Level::Debug, recipe::LOG_FORMAT_DEBUG);
+        recipe::per_test_file_logger(TEST_DIR, Level::Debug)
+            .add_sink(on_failure)
+            .build()
+            .expect("setup log");
+    });
+}
+
+fn read_test_log(name: &str) -> String {
+    read_to_string(Path::new(TEST_DIR).join(format!("{}.log", name))).expect("read log")
+}
+
+#[logfn]
+fn do_work(tag: &str) {
+    for i in 0..100 {
+        info!("{} line {}", tag, i);
+    }
+}
+
+#[test]
+#[logfn]
+fn test_per_test_a() {
+    setup();
+    do_work("aaa");
+    let content = read_test_log("test_per_test_a");
+    assert!(content.contains("aaa line 99"));
+    assert!(!content.contains("bbb"));
+}
+
+#[test]
+#[logfn]
+fn test_per_test_b() {
+    setup();
+    do_work("bbb");
+    let content = read_test_log("test_per_test_b");
+    assert!(content.contains("bbb line 99"));
+    assert!(!content.contains("aaa"));
+}
+
+#[tokio::test]
+#[logfn]
+async fn test_per_test_async() {
+    setup();
+    info!("async ccc");
+    tokio::task::yield_now().await;
+    info!("async ddd");
+    let content = read_test_log("test_per_test_async");
+    assert!(content.contains("async ccc"));
+    assert!(content.contains("async ddd"));
+}
+
+#[test]
+fn test_per_test_failure() {
+    setup();
+
+    #[logfn]
+    fn failing() {
+        info!("before failure");
+        panic!("failure in scope");
+    }
+    #[logfn]
+    fn passing() {
+        info!("before success");
+    }
+    let th = thread::Builder::new().name("failing_case".to_string()).spawn(failing).unwrap();
+    assert!(th.join().is_err());
+    let th = thread::Builder::new().name("passing_case".to_string()).spawn(passing).unwrap();
+    th.join().unwrap();
+    let content = read_test_log("failing_case");
+    assert!(content.contains("before failure"));
+    assert!(read_test_log("passing_case").contains("before success"));
+    // Only the failed scope is dumped by on_failure
+    let dump = read_to_string(FAILURE_LOG).expect("read failure log");
+    assert!(dump.contains("---- log of failing_case ----"));
+    assert!(dump.contains("before failure"));
+    assert!(!dump.contains("passing_case"));
+    assert!(!dump.contains("before success"));
+}
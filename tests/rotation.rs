@@ -15,6 +15,25 @@ fn read_files<P: AsRef<Path>>(dir: P) -> Vec<DirEntry> {
     read_dir(dir).unwrap().filter_map(|entry| entry.ok()).collect()
 }
 
+/// Whether the filesystem keeps the birth time (statx), or the creation time in the user xattr
+fn create_time_persisted(path: &Path) -> bool {
+    use std::os::unix::io::AsRawFd;
+    let f = File::open(path).unwrap();
+    if f.metadata().unwrap().created().is_ok() {
+        return true;
+    }
+    let mut buf = [0u8; 40];
+    let r = unsafe {
+        libc::fgetxattr(
+            f.as_raw_fd(),
+            b"user.captains_log.create_time\0".as_ptr() as *const libc::c_char,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    };
+    r > 0
+}
+
 #[rstest]
 #[case(Some(3))]
 #[case(Some(2))]
@@ -178,3 +197,925 @@ fn test_buffered_file_rotation_by_size_archive_dir_compress(#[case] max_files: O
         assert_eq!(files.len(), 5);
     }
 }
+
+#[test]
+fn test_buffered_file_rotation_by_age_manual_clock() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let clock = std::sync::Arc::new(ManualClock::new(std::time::SystemTime::now()));
+    let rotation = Rotation::by_age(Age::Hour, false, "%Y%m%d%H%M%S", None);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(base_path, Level::Debug, rotation)
+        .clock(Clock::Manual(clock.clone()))
+        .test()
+        .build()
+        .expect("setup");
+
+    info!("before");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 1);
+    clock.advance(std::time::Duration::from_secs(30 * 60));
+    info!("half an hour later");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 1);
+    clock.advance(std::time::Duration::from_secs(31 * 60));
+    info!("one hour later");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 2);
+}
+
+#[test]
+fn test_buffered_file_rotation_by_age_once() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let clock = std::sync::Arc::new(ManualClock::new(std::time::SystemTime::now()));
+    let rotation = Rotation::by_age(Age::Hour, false, "%Y%m%d%H%M%S", None);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(base_path, Level::Debug, rotation)
+        .clock(Clock::Manual(clock.clone()))
+        .test()
+        .build()
+        .expect("setup");
+
+    info!("before");
+    logger().flush();
+    clock.advance(std::time::Duration::from_secs(61 * 60));
+    info!("one hour later");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 2);
+    // The age restarts from the rotation, not rotated again on each flush
+    for _ in 0..3 {
+        clock.advance(std::time::Duration::from_secs(60));
+        info!("one minute later");
+        logger().flush();
+        assert_eq!(read_files(TEST_DIR).len(), 2);
+    }
+    clock.advance(std::time::Duration::from_secs(60 * 60));
+    info!("two hours later");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 3);
+}
+
+#[rstest]
+#[case(Some(3))]
+#[case(None)]
+fn test_raw_file_rotation_by_size(#[case] max_files: Option<usize>) {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let rotation = Rotation::by_size(1024 * 4 * 2, max_files);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::raw_rotated_file_logger(base_path, Level::Debug, rotation)
+        .test()
+        .build()
+        .expect("setup");
+
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    let files = read_files(TEST_DIR);
+    // + current + lock file
+    if let Some(_max_files) = max_files {
+        assert_eq!(files.len(), _max_files + 2);
+    } else {
+        assert!(files.len() >= 7);
+    }
+}
+
+/// Two sinks on the same path, act like two processes writing to the same file
+#[test]
+fn test_raw_file_rotation_shared() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let rotation = || Rotation::by_size(1024 * 4 * 2, None);
+    let sink =
+        || LogRawFile::new(TEST_DIR, "rotation_test.log", Level::Debug, recipe::LOG_FORMAT_DEBUG);
+    Builder::default()
+        .add_sink(sink().rotation(rotation()))
+        .add_sink(sink().rotation(rotation()))
+        .test()
+        .build()
+        .expect("setup");
+
+    let mut ths = Vec::new();
+    for t in 0..4 {
+        ths.push(std::thread::spawn(move || {
+            for _i in 0..500 {
+                info!("thread {} test {}", t, _i);
+            }
+        }));
+    }
+    for th in ths {
+        th.join().unwrap();
+    }
+    logger().flush();
+    let mut lines = 0;
+    let mut archives = 0;
+    for entry in read_files(TEST_DIR) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.ends_with(".lock") {
+            continue;
+        }
+        if name != "rotation_test.log" {
+            archives += 1;
+        }
+        lines += read_to_string(entry.path()).unwrap().lines().count();
+    }
+    assert!(archives > 1);
+    // No line lost during rotation
+    assert_eq!(lines, 4 * 500 * 2);
+}
+
+#[test]
+fn test_buffered_file_rotation_aligned() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let time = |s: &str| -> std::time::SystemTime {
+        chrono::DateTime::parse_from_rfc3339(s).unwrap().into()
+    };
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    let setup = |clock: &std::sync::Arc<ManualClock>| {
+        let rotation = Rotation::by_age(Age::Day, true, "%Y%m%d", None).align(TimeZone::Utc);
+        recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+            .clock(Clock::Manual(clock.clone()))
+            .test()
+            .build()
+            .expect("setup");
+    };
+    let clock = std::sync::Arc::new(ManualClock::new(time("2024-01-01T23:30:00Z")));
+    setup(&clock);
+    info!("day 1");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 1);
+    clock.advance(std::time::Duration::from_secs(20 * 60));
+    info!("day 1 end");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 1);
+    clock.advance(std::time::Duration::from_secs(20 * 60));
+    info!("day 2");
+    logger().flush();
+    let archive = Path::new(TEST_DIR).join("rotation_test.log.20240101");
+    assert!(archive.exists());
+    assert!(read_to_string(&archive).unwrap().contains("day 1 end"));
+    assert!(!read_to_string(&archive).unwrap().contains("day 2"));
+    assert!(read_to_string(&base_path).unwrap().contains("day 2"));
+
+    // Restart on day 3, the file last written on day 2 should be rotated.
+    let f = OpenOptions::new().append(true).open(&base_path).unwrap();
+    f.set_modified(time("2024-01-02T12:00:00Z")).unwrap();
+    drop(f);
+    let clock = std::sync::Arc::new(ManualClock::new(time("2024-01-03T01:00:00Z")));
+    setup(&clock);
+    info!("day 3");
+    logger().flush();
+    assert!(Path::new(TEST_DIR).join("rotation_test.log.20240102").exists());
+    assert_eq!(read_files(TEST_DIR).len(), 3);
+}
+
+#[rstest]
+#[case(Age::Minute, true, "%Y%m%d%H%M", "2024-01-31T23:59:30Z", 40, "202401312359")]
+#[case(Age::Week, true, "%Y%m%d", "2024-01-07T23:00:00Z", 2 * 3600, "20240101")]
+#[case(Age::Month, true, "%Y%m", "2024-01-31T23:00:00Z", 2 * 3600, "202401")]
+#[case(Age::Every(std::time::Duration::from_secs(15 * 60)), true, "%Y%m%d%H%M", "2024-01-01T10:14:00Z", 120, "202401011000")]
+#[case(Age::Month, false, "%Y%m", "2024-03-15T12:00:00Z", 32 * 24 * 3600, "202403")]
+fn test_buffered_file_rotation_by_age_interval(
+    #[case] age: Age, #[case] align: bool, #[case] time_fmt: &'static str, #[case] start: &str,
+    #[case] advance_secs: u64, #[case] archive: &str,
+) {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let start: std::time::SystemTime = chrono::DateTime::parse_from_rfc3339(start).unwrap().into();
+    let clock = std::sync::Arc::new(ManualClock::new(start));
+    let mut rotation = Rotation::by_age(age, true, time_fmt, None);
+    if align {
+        rotation = rotation.align(TimeZone::Utc);
+    }
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+        .clock(Clock::Manual(clock.clone()))
+        .test()
+        .build()
+        .expect("setup");
+    info!("before");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 1);
+    clock.advance(std::time::Duration::from_secs(advance_secs));
+    info!("after");
+    logger().flush();
+    let files = read_files(TEST_DIR);
+    assert_eq!(files.len(), 2);
+    assert!(Path::new(TEST_DIR).join(format!("rotation_test.log.{}", archive)).exists());
+}
+
+#[rstest]
+#[case(Upkeep::Size(1024 * 30), 2)]
+#[case(Upkeep::Limits { max_age: None, max_count: Some(1), max_size: Some(1024 * 30) }, 1)]
+#[case(Upkeep::Limits { max_age: Some(chrono::TimeDelta::days(1)), max_count: Some(5), max_size: Some(1024 * 30) }, 2)]
+fn test_buffered_file_rotation_upkeep_size(#[case] upkeep: Upkeep, #[case] archives: usize) {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let rotation = Rotation::by_size(1024 * 8, None).upkeep(upkeep);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(base_path, Level::Debug, rotation)
+        .test()
+        .build()
+        .expect("setup");
+
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    let files = read_files(TEST_DIR);
+    assert_eq!(files.len(), archives + 1);
+}
+
+#[test]
+fn test_buffered_file_rotation_upkeep_age_by_mtime() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let rotation =
+        Rotation::by_size(1024 * 8, None).upkeep(Upkeep::Age(chrono::TimeDelta::hours(1)));
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+        .test()
+        .build()
+        .expect("setup");
+
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    let files = read_files(TEST_DIR);
+    assert!(files.len() > 3);
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 3600);
+    for entry in files {
+        if entry.path() != base_path {
+            let f = OpenOptions::new().append(true).open(entry.path()).unwrap();
+            f.set_modified(old).unwrap();
+        }
+    }
+    // Trigger one more rotation
+    for _i in 0..200 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 2);
+}
+
+#[test]
+fn test_buffered_file_rotation_compress_recover() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    create_dir_all(TEST_DIR).unwrap();
+    let dir = Path::new(TEST_DIR);
+    // Left by a crash during compression
+    write(dir.join("rotation_test.log.1"), "archive 1\n").unwrap();
+    write(dir.join("rotation_test.log.1.gz"), "incomplete").unwrap();
+    write(dir.join("rotation_test.log.2.gz.tmp"), "incomplete").unwrap();
+    write(dir.join("rotation_test.log.3.gz"), "complete").unwrap();
+    let rotation = Rotation::by_size(1024 * 4 * 2, None).compress_exclude(0);
+    recipe::buffered_rotated_file_logger(dir.join("rotation_test.log"), Level::Debug, rotation)
+        .test()
+        .build()
+        .expect("setup");
+    assert!(dir.join("rotation_test.log.1").exists());
+    assert!(!dir.join("rotation_test.log.1.gz").exists());
+    assert!(!dir.join("rotation_test.log.2.gz.tmp").exists());
+    assert!(dir.join("rotation_test.log.3.gz").exists());
+
+    for _i in 0..200 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    for entry in read_files(TEST_DIR) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        assert!(!name.ends_with(".tmp"));
+        assert!(name == "rotation_test.log" || name.ends_with(".gz"), "{}", name);
+    }
+}
+
+#[test]
+fn test_buffered_file_rotation_compress_during_setup() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    create_dir_all(TEST_DIR).unwrap();
+    let dir = Path::new(TEST_DIR);
+    // Incompressible content, so that the compression takes a while
+    let mut x: u64 = 0x9e3779b97f4a7c15;
+    let content: Vec<u8> = (0..(16 << 20))
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        })
+        .collect();
+    write(dir.join("rotation_test.log"), &content).unwrap();
+    let setup = |level, rotate_on_start| {
+        let mut rotation =
+            Rotation::by_size(1 << 30, None).compress_exclude(0).codec(Codec::Gzip(9));
+        if rotate_on_start {
+            rotation = rotation.rotate_on_start();
+        }
+        recipe::buffered_rotated_file_logger(dir.join("rotation_test.log"), level, rotation)
+            .test()
+            .build()
+            .expect("setup");
+    };
+    setup(Level::Debug, true);
+    // Not flush, which waits for the compression
+    info!("rotate");
+    let tmp = dir.join("rotation_test.log.1.gz.tmp");
+    for _ in 0..100 {
+        if tmp.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    // Setup again while the cleanup thread of the previous sink is compressing
+    assert!(tmp.exists());
+    setup(Level::Info, false);
+
+    let archive = dir.join("rotation_test.log.1.gz");
+    for _ in 0..600 {
+        if archive.exists() && !dir.join("rotation_test.log.1").exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let mut decoded = Vec::new();
+    let mut decoder = flate2::read::GzDecoder::new(File::open(&archive).expect("archive"));
+    std::io::Read::read_to_end(&mut decoder, &mut decoded).expect("decode");
+    assert!(decoded == content);
+    assert!(!tmp.exists());
+}
+
+#[test]
+fn test_raw_file_rotation_multi_process() {
+    const CHILDREN: usize = 3;
+    const LINES: usize = 600;
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    if let Ok(id) = std::env::var("CAPTAINS_LOG_TEST_CHILD") {
+        // Run by the child process, the parent holds the test lock
+        let rotation = Rotation::by_size(16 * 1024, None).compress_exclude(0).codec(Codec::Gzip(1));
+        recipe::raw_rotated_file_logger(&base_path, Level::Debug, rotation)
+            .test()
+            .build()
+            .expect("setup");
+        for i in 0..LINES {
+            info!("child {} line {}", id, i);
+            // Idle for longer than the check interval from time to time
+            if i % 100 == 99 {
+                std::thread::sleep(std::time::Duration::from_millis(150));
+            }
+        }
+        logger().flush();
+        return;
+    }
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let children: Vec<_> = (0..CHILDREN)
+        .map(|id| {
+            std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["test_raw_file_rotation_multi_process", "--exact", "--test-threads=1"])
+                .env("CAPTAINS_LOG_TEST_CHILD", id.to_string())
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .expect("spawn")
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+    let mut content = String::new();
+    for entry in read_files(TEST_DIR) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.ends_with(".gz") {
+            let mut decoder = flate2::read::GzDecoder::new(File::open(entry.path()).unwrap());
+            std::io::Read::read_to_string(&mut decoder, &mut content).expect("decode");
+        } else if !name.ends_with(".lock") {
+            content.push_str(&read_to_string(entry.path()).unwrap());
+        }
+    }
+    // No line is written to the archive after it's compressed
+    for id in 0..CHILDREN {
+        for i in 0..LINES {
+            let line = format!("child {} line {}\n", id, i);
+            assert_eq!(content.matches(&line).count(), 1, "{}", line);
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_buffered_file_rotation_zstd() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let rotation = Rotation::by_size(1024 * 4 * 2, Some(3))
+        .compress_exclude(1)
+        .codec(rotation::Codec::Zstd(3))
+        .compress_idle_io();
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+        .test()
+        .build()
+        .expect("setup");
+
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    let files = read_files(TEST_DIR);
+    assert_eq!(files.len(), 4);
+    let compressed: Vec<DirEntry> =
+        files.into_iter().filter(|e| e.file_name().to_string_lossy().ends_with(".zst")).collect();
+    assert_eq!(compressed.len(), 2);
+    let content = zstd::decode_all(File::open(compressed[0].path()).unwrap()).unwrap();
+    assert!(String::from_utf8(content).unwrap().contains("test "));
+}
+
+#[rstest]
+#[case(None)]
+#[case(Some(0))]
+#[case(Some(1))]
+fn test_buffered_file_rotation_hooks(#[case] compress_exclude: Option<usize>) {
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let archived = Arc::new(Mutex::new(Vec::<PathBuf>::new()));
+    let deleted = Arc::new(Mutex::new(Vec::<PathBuf>::new()));
+    let _archived = archived.clone();
+    let _deleted = deleted.clone();
+    let mut rotation = Rotation::by_size(1024 * 4 * 2, Some(2))
+        .on_archive(move |path| {
+            assert!(path.exists());
+            _archived.lock().unwrap().push(path.to_path_buf());
+        })
+        .on_delete(move |path| {
+            assert!(path.exists());
+            _deleted.lock().unwrap().push(path.to_path_buf());
+        });
+    if let Some(n) = compress_exclude {
+        rotation = rotation.compress_exclude(n);
+    }
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+        .test()
+        .build()
+        .expect("setup");
+
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    let archived = archived.lock().unwrap();
+    let deleted = deleted.lock().unwrap();
+    let (compressed, plain): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        archived.iter().partition(|path| path.to_string_lossy().ends_with(".gz"));
+    // Every archive is notified once after rename, or once compressed
+    let rotated = match compress_exclude {
+        None => {
+            assert!(compressed.is_empty());
+            plain.len()
+        }
+        Some(0) => {
+            assert!(plain.is_empty());
+            compressed.len()
+        }
+        Some(_) => {
+            // The newest is not compressed yet
+            assert_eq!(compressed.len(), plain.len() - 1);
+            plain.len()
+        }
+    };
+    assert!(rotated > 2);
+    assert_eq!(deleted.len(), rotated - 2);
+}
+
+#[test]
+fn test_buffered_file_rotation_hook_panic() {
+    use std::sync::{Arc, Mutex};
+
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let _errors = errors.clone();
+    let rotation = Rotation::by_size(1024 * 4 * 2, Some(2))
+        .compress_exclude(0)
+        .on_archive(|path| panic!("hook {}", path.display()));
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+        .on_error(move |e: &SinkError| _errors.lock().unwrap().push(e.kind))
+        .test()
+        .build()
+        .expect("setup");
+
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    let errors = errors.lock().unwrap();
+    assert!(errors.len() > 2);
+    assert!(errors.iter().all(|kind| *kind == SinkErrorKind::Rotate));
+    // The cleanup goes on after the panic
+    let files: Vec<String> = read_dir(TEST_DIR)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name != "rotation_test.log")
+        .collect();
+    assert_eq!(files.len(), 2, "{:?}", files);
+    assert!(files.iter().all(|name| name.ends_with(".gz")), "{:?}", files);
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn test_file_rotation_on_start(#[case] raw: bool) {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    let setup = |on_start: bool| {
+        let mut rotation = Rotation::by_size(1024 * 1024, None);
+        if on_start {
+            rotation = rotation.rotate_on_start();
+        }
+        let builder = if raw {
+            recipe::raw_rotated_file_logger(&base_path, Level::Debug, rotation)
+        } else {
+            recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+        };
+        builder.test().build().expect("setup");
+    };
+    setup(false);
+    info!("run 1");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 1);
+    // Restart with rotate_on_start
+    setup(true);
+    info!("run 2");
+    logger().flush();
+    let archive = Path::new(TEST_DIR).join("rotation_test.log.1");
+    assert!(read_to_string(&archive).unwrap().contains("run 1"));
+    let content = read_to_string(&base_path).unwrap();
+    assert!(content.contains("run 2"));
+    assert!(!content.contains("run 1"));
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn test_file_rotation_symlink(#[case] raw: bool) {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let time = |s: &str| -> std::time::SystemTime {
+        chrono::DateTime::parse_from_rfc3339(s).unwrap().into()
+    };
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    let clock = std::sync::Arc::new(ManualClock::new(time("2024-01-01T23:30:00Z")));
+    let rotation = Rotation::by_age(Age::Day, false, "%Y%m%d", None).align(TimeZone::Utc).symlink();
+    let builder = if raw {
+        recipe::raw_rotated_file_logger(&base_path, Level::Debug, rotation)
+    } else {
+        recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+    };
+    builder.clock(Clock::Manual(clock.clone())).test().build().expect("setup");
+    info!("day 1");
+    logger().flush();
+    assert_eq!(read_link(&base_path).unwrap(), Path::new("rotation_test.log.20240101"));
+    clock.advance(std::time::Duration::from_secs(40 * 60));
+    // LogRawFile checks the rotation after write
+    logger().flush();
+    info!("day 2");
+    logger().flush();
+    assert_eq!(read_link(&base_path).unwrap(), Path::new("rotation_test.log.20240102"));
+    let archive = Path::new(TEST_DIR).join("rotation_test.log.20240101");
+    assert!(read_to_string(&archive).unwrap().contains("day 1"));
+    assert!(!read_to_string(&archive).unwrap().contains("day 2"));
+    assert!(read_to_string(&base_path).unwrap().contains("day 2"));
+}
+
+#[test]
+fn test_buffered_file_rotation_by_age_restart() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    let setup = |clock: &std::sync::Arc<ManualClock>| {
+        let rotation = Rotation::by_age(Age::Hour, false, "%Y%m%d%H%M", None);
+        recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+            .clock(Clock::Manual(clock.clone()))
+            .test()
+            .build()
+            .expect("setup");
+    };
+    let start = std::time::SystemTime::now();
+    let clock = std::sync::Arc::new(ManualClock::new(start));
+    setup(&clock);
+    info!("before restart");
+    logger().flush();
+    if !create_time_persisted(&base_path) {
+        // Falls back to mtime on restart
+        return;
+    }
+    // Written recently, but created more than an hour ago
+    let f = OpenOptions::new().append(true).open(&base_path).unwrap();
+    f.set_modified(start + std::time::Duration::from_secs(7200)).unwrap();
+    drop(f);
+
+    let clock = std::sync::Arc::new(ManualClock::new(start + std::time::Duration::from_secs(7300)));
+    setup(&clock);
+    info!("after restart");
+    logger().flush();
+    assert_eq!(read_files(TEST_DIR).len(), 2);
+    assert!(!read_to_string(&base_path).unwrap().contains("before restart"));
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn test_file_rotation_error_reported_once(#[case] buffered: bool) {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let _errors = errors.clone();
+    let rotation = Rotation::by_size(100, None).archive_dir(TEST_DIR_OLD);
+    let builder = if buffered {
+        recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+    } else {
+        recipe::raw_rotated_file_logger(&base_path, Level::Debug, rotation)
+    };
+    builder
+        .on_error(move |e: &SinkError| _errors.lock().unwrap().push(e.kind))
+        .test()
+        .build()
+        .expect("setup");
+    // A regular file in place of archive_dir, the rename fails with ENOTDIR
+    remove_dir_all(TEST_DIR_OLD).unwrap();
+    write(TEST_DIR_OLD, "").unwrap();
+    for i in 0..10 {
+        info!("{} {}", i, "x".repeat(100));
+        logger().flush();
+    }
+    assert_eq!(*errors.lock().unwrap(), [SinkErrorKind::Rotate]);
+    // Recovered
+    remove_file(TEST_DIR_OLD).unwrap();
+    create_dir_all(TEST_DIR_OLD).unwrap();
+    info!("recovered");
+    logger().flush();
+    assert!(read_dir(TEST_DIR_OLD).unwrap().count() > 0);
+    assert_eq!(errors.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_file_rotation_setup_error() {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    create_dir_all(TEST_DIR).unwrap();
+    // A regular file in place of the dir
+    let not_dir = Path::new(TEST_DIR).join("not_dir");
+    write(&not_dir, "").unwrap();
+
+    let rotation = Rotation::by_size(1024, None);
+    let r = recipe::buffered_rotated_file_logger(not_dir.join("test.log"), Level::Debug, rotation)
+        .test()
+        .build();
+    assert!(r.is_err());
+
+    let rotation = Rotation::by_size(1024, None);
+    let r = recipe::raw_rotated_file_logger(not_dir.join("test.log"), Level::Debug, rotation)
+        .test()
+        .build();
+    assert!(r.is_err());
+
+    let rotation = Rotation::by_size(1024, None).archive_dir(not_dir.join("old"));
+    let r = recipe::buffered_rotated_file_logger(
+        Path::new(TEST_DIR).join("rotation_test.log"),
+        Level::Debug,
+        rotation,
+    )
+    .test()
+    .build();
+    assert!(r.is_err());
+
+    let mut rotation = Rotation::by_size(1024, None);
+    rotation.by_size = None;
+    let r = recipe::raw_rotated_file_logger(
+        Path::new(TEST_DIR).join("rotation_test.log"),
+        Level::Debug,
+        rotation,
+    )
+    .test()
+    .build();
+    assert!(r.is_err());
+
+    // align requires by_age
+    let rotation = Rotation::by_size(1024, None).align(TimeZone::Utc);
+    let r = recipe::raw_rotated_file_logger(
+        Path::new(TEST_DIR).join("rotation_test.log"),
+        Level::Debug,
+        rotation,
+    )
+    .test()
+    .build();
+    assert!(r.is_err());
+
+    // The path without file name is reported by build() instead of panic
+    for path in ["", "/"] {
+        assert!(recipe::raw_file_logger(path, Level::Debug).test().build().is_err());
+        let rotation = Rotation::by_size(1024, None);
+        let r = recipe::raw_rotated_file_logger(path, Level::Debug, rotation).test().build();
+        assert!(r.is_err());
+        assert!(recipe::buffered_file_logger(path, Level::Debug).test().build().is_err());
+        let rotation = Rotation::by_size(1024, None);
+        let r = recipe::buffered_rotated_file_logger(path, Level::Debug, rotation).test().build();
+        assert!(r.is_err());
+    }
+
+    // aligned Age::Every should be whole seconds
+    for d in [std::time::Duration::from_millis(500), std::time::Duration::from_millis(1500)] {
+        let rotation =
+            Rotation::by_age(Age::Every(d), false, "%Y%m%d%H%M%S", None).align(TimeZone::Utc);
+        let r = recipe::raw_rotated_file_logger(
+            Path::new(TEST_DIR).join("rotation_test.log"),
+            Level::Debug,
+            rotation,
+        )
+        .test()
+        .build();
+        assert!(r.is_err());
+    }
+}
+
+#[test]
+fn test_file_rotation_file_options() {
+    use std::os::unix::fs::PermissionsExt;
+
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let mode = |p: &Path| metadata(p).unwrap().permissions().mode() & 0o7777;
+    let umask = read_to_string("/proc/self/status")
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("Umask:"))
+        .map(|umask| u32::from_str_radix(umask.trim(), 8).unwrap())
+        .unwrap();
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    // Each dir created has the mode
+    let archive_dir = Path::new(TEST_DIR_OLD).join("nested");
+    let rotation = Rotation::by_size(1024 * 4 * 2, None)
+        .compress_exclude(1)
+        .archive_dir(&archive_dir)
+        .file_options(FileOptions::default().mode(0o600).dir_mode(0o700));
+    recipe::buffered_rotated_file_logger(&base_path, Level::Debug, rotation)
+        .test()
+        .build()
+        .expect("setup");
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    // Default to umask
+    assert_eq!(mode(&base_path), 0o666 & !umask);
+    assert_eq!(mode(Path::new(TEST_DIR_OLD)), 0o700);
+    assert_eq!(mode(&archive_dir), 0o700);
+    let archives = read_files(&archive_dir);
+    assert!(archives.iter().any(|e| e.file_name().to_string_lossy().ends_with(".gz")));
+    for entry in archives {
+        assert_eq!(mode(&entry.path()), 0o600, "{:?}", entry.path());
+    }
+
+    let _ = remove_dir_all(TEST_DIR);
+    let opts = FileOptions::default().mode(0o640).dir_mode(0o750);
+    // The dirs are created by the constructor, and the mode is applied on build
+    let log_dir = Path::new(TEST_DIR).join("nested");
+    let file =
+        LogRawFile::new(&log_dir, "rotation_test.log", Level::Debug, recipe::LOG_FORMAT_DEBUG)
+            .file_options(opts)
+            .rotation(Rotation::by_size(1024 * 4 * 2, None));
+    assert!(log_dir.is_dir());
+    Builder::default().add_sink(file).test().build().expect("setup");
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    assert_eq!(mode(Path::new(TEST_DIR)), 0o750);
+    assert_eq!(mode(&log_dir), 0o750);
+    for entry in read_files(&log_dir) {
+        assert_eq!(mode(&entry.path()), 0o640, "{:?}", entry.path());
+    }
+
+    // Refuse to open the symlink
+    let _ = remove_dir_all(TEST_DIR);
+    create_dir_all(TEST_DIR).unwrap();
+    write(Path::new(TEST_DIR).join("target.log"), "").unwrap();
+    std::os::unix::fs::symlink("target.log", &base_path).unwrap();
+    let file =
+        LogRawFile::new(TEST_DIR, "rotation_test.log", Level::Debug, recipe::LOG_FORMAT_DEBUG)
+            .file_options(FileOptions::default().nofollow());
+    assert!(Builder::default().add_sink(file).test().build().is_err());
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn test_file_disk_guard(#[case] buffered: bool) {
+    lock_file!();
+    let _ = remove_dir_all(TEST_DIR);
+    let base_path = Path::new(TEST_DIR).join("rotation_test.log");
+    let setup = |rotation: Rotation, guard: Option<DiskGuard>| {
+        let format = recipe::LOG_FORMAT_DEBUG;
+        let builder = if buffered {
+            let mut file = LogBufFile::new(TEST_DIR, "rotation_test.log", Level::Debug, format, 0)
+                .rotation(rotation);
+            if let Some(guard) = guard {
+                file = file.disk_guard(guard);
+            }
+            Builder::default().add_sink(file)
+        } else {
+            let mut file = LogRawFile::new(TEST_DIR, "rotation_test.log", Level::Debug, format)
+                .rotation(rotation);
+            if let Some(guard) = guard {
+                file = file.disk_guard(guard);
+            }
+            Builder::default().add_sink(file)
+        };
+        builder.test().build().expect("setup");
+    };
+    let archive_count = || {
+        read_files(TEST_DIR)
+            .iter()
+            .filter(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                name.starts_with("rotation_test.log.") && !name.ends_with(".lock")
+            })
+            .count()
+    };
+    // Never engaged
+    setup(Rotation::by_size(1024, None), Some(DiskGuard::new(0, DiskAction::PurgeArchives)));
+    for _i in 0..1000 {
+        info!("test {}", _i);
+    }
+    logger().flush();
+    assert!(archive_count() > 2);
+
+    // Always engaged, all the archives are purged
+    let guard = DiskGuard::new(u64::MAX, DiskAction::PurgeArchives);
+    setup(Rotation::by_size(1024 * 1024, None), Some(guard));
+    logger().flush();
+    assert_eq!(archive_count(), 0);
+    let content = read_to_string(&base_path).unwrap();
+    assert!(content.lines().last().unwrap().contains("PurgeArchives"));
+
+    // Drop Info and Debug
+    let _ = remove_file(&base_path);
+    let guard = DiskGuard::new(u64::MAX, DiskAction::DropBelow(Level::Warn));
+    setup(Rotation::by_size(1024 * 1024, Some(1)), Some(guard));
+    info!("dropped info");
+    warn!("written warn");
+    logger().flush();
+    let content = read_to_string(&base_path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines[0].contains("[WARN]") && lines[0].contains("DropBelow(Warn)"));
+    assert!(lines[1].ends_with("written warn"));
+
+    // Write to stderr instead
+    let _ = remove_file(&base_path);
+    let guard = DiskGuard::new(u64::MAX, DiskAction::Fallback);
+    setup(Rotation::by_size(1024 * 1024, Some(2)), Some(guard));
+    error!("to stderr");
+    logger().flush();
+    assert_eq!(metadata(&base_path).unwrap().len(), 0);
+
+    // Write to the secondary of LogFailover instead
+    let _ = remove_file(&base_path);
+    let guard = DiskGuard::new(u64::MAX, DiskAction::Fallback);
+    let secondary =
+        LogRawFile::new(TEST_DIR, "secondary.log", Level::Debug, recipe::LOG_FORMAT_DEBUG);
+    let builder = if buffered {
+        let file = LogBufFile::new(
+            TEST_DIR,
+            "rotation_test.log",
+            Level::Debug,
+            recipe::LOG_FORMAT_DEBUG,
+            0,
+        )
+        .disk_guard(guard);
+        Builder::default().add_sink(LogFailover::new(file, secondary))
+    } else {
+        let file =
+            LogRawFile::new(TEST_DIR, "rotation_test.log", Level::Debug, recipe::LOG_FORMAT_DEBUG)
+                .disk_guard(guard);
+        Builder::default().add_sink(LogFailover::new(file, secondary))
+    };
+    builder.test().build().expect("setup");
+    error!("to secondary");
+    logger().flush();
+    assert_eq!(metadata(&base_path).unwrap().len(), 0);
+    let content = read_to_string(Path::new(TEST_DIR).join("secondary.log")).unwrap();
+    let lines: Vec<&str> = content.lines().filter(|line| !line.contains("disk free")).collect();
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert!(lines[0].ends_with("to secondary"));
+}